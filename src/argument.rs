@@ -0,0 +1,231 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::*;
+
+
+/// Declaration of a job argument, either just its name or with additional information
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ArgDeclaration {
+    Name(String),
+    Detailed(ArgSpec),
+}
+
+/// Argument with help text and constraints on its values
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArgSpec {
+    name: String,
+
+    /// Help text shown by `--list` and `zinn help`
+    #[serde(default)]
+    help: Option<String>,
+
+    /// Type of the values
+    ///
+    /// See also [`ArgType`].
+    #[serde(default, rename = "type")]
+    kind: ArgType,
+
+    /// Allowed values (required for enums)
+    #[serde(default)]
+    values: Vec<String>,
+
+    /// Regex that values have to match completely
+    ///
+    /// Always parsed, so it is reported instead of ignored without the `regex` feature.
+    #[serde(default)]
+    regex: Option<String>,
+
+    /// Paths have to exist (relative to the directory of the job)
+    #[serde(default)]
+    exists: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArgType {
+    #[default]
+    String,
+    Int,
+    Bool,
+    Path,
+    Enum,
+}
+
+
+impl ArgDeclaration {
+    pub fn name(&self) -> &str {
+        match self {
+            ArgDeclaration::Name(name) => name,
+            ArgDeclaration::Detailed(spec) => &spec.name,
+        }
+    }
+
+    pub fn help(&self) -> Option<&str> {
+        match self {
+            ArgDeclaration::Name(_) => None,
+            ArgDeclaration::Detailed(spec) => spec.help.as_deref(),
+        }
+    }
+
+    /// Check that the constraints of this argument can be satisfied at all
+    pub fn check_declaration(&self) -> Result<(), &'static str> {
+        let spec = match self {
+            ArgDeclaration::Name(_) => return Ok(()),
+            ArgDeclaration::Detailed(spec) => spec,
+        };
+
+        if spec.kind == ArgType::Enum && spec.values.is_empty() {
+            return Err("enums require a list of values");
+        }
+        #[cfg(not(feature = "regex"))]
+        if spec.regex.is_some() {
+            return Err("regexes are not supported without the `regex` feature");
+        }
+
+        Ok(())
+    }
+
+    /// Description of the values accepted by this argument, if restricted
+    pub fn expected(&self) -> Option<String> {
+        let spec = match self {
+            ArgDeclaration::Name(_) => return None,
+            ArgDeclaration::Detailed(spec) => spec,
+        };
+
+        let mut expected = Vec::new();
+        match spec.kind {
+            ArgType::String | ArgType::Enum => (),
+            ArgType::Int => expected.push(String::from("an integer")),
+            ArgType::Bool => expected.push(String::from("true or false")),
+            ArgType::Path if spec.exists => expected.push(String::from("an existing path")),
+            ArgType::Path => expected.push(String::from("a path")),
+        }
+        if !spec.values.is_empty() || spec.kind == ArgType::Enum {
+            expected.push(format!("one of [{}]", spec.values.join(", ")));
+        }
+        #[cfg(feature = "regex")]
+        if let Some(regex) = &spec.regex {
+            expected.push(format!("matching /{}/", regex));
+        }
+
+        (!expected.is_empty()).then(|| expected.join(", "))
+    }
+
+    /// Check whether a value is accepted by this argument
+    ///
+    /// Paths are relative to `dir`.
+    pub fn accepts(&self, value: &str, dir: &Path) -> ZinnResult<bool> {
+        let spec = match self {
+            ArgDeclaration::Name(_) => return Ok(true),
+            ArgDeclaration::Detailed(spec) => spec,
+        };
+
+        let valid_type = match spec.kind {
+            ArgType::String | ArgType::Enum => true,
+            ArgType::Int => value.parse::<i64>().is_ok(),
+            ArgType::Bool => value == "true" || value == "false",
+            ArgType::Path => !value.is_empty() && (!spec.exists || dir.join(value).exists()),
+        };
+        if !valid_type {
+            return Ok(false);
+        }
+
+        if (!spec.values.is_empty() || spec.kind == ArgType::Enum) && !spec.values.iter().any(|v| v == value) {
+            return Ok(false);
+        }
+
+        #[cfg(feature = "regex")]
+        if let Some(regex) = &spec.regex {
+            let regex = regex_lite::Regex::new(&format!("^(?:{})$", regex))?;
+            if !regex.is_match(value) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arg(yaml: &str) -> ArgDeclaration {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn accepts(arg: &ArgDeclaration, value: &str) -> bool {
+        arg.accepts(value, Path::new(".")).unwrap()
+    }
+
+    #[test]
+    fn plain_name() {
+        let arg = arg("path");
+        assert_eq!(arg.name(), "path");
+        assert!(arg.check_declaration().is_ok());
+        assert!(accepts(&arg, ""));
+        assert_eq!(arg.expected(), None);
+    }
+
+    #[test]
+    fn enum_values() {
+        let arg = arg("{name: mode, type: enum, values: [debug, release]}");
+        assert!(accepts(&arg, "debug"));
+        assert!(accepts(&arg, "release"));
+        assert!(!accepts(&arg, "Debug"));
+        assert!(!accepts(&arg, ""));
+        assert_eq!(arg.expected().as_deref(), Some("one of [debug, release]"));
+    }
+
+    #[test]
+    fn enum_without_values() {
+        assert!(arg("{name: mode, type: enum}").check_declaration().is_err());
+    }
+
+    #[test]
+    fn int_values() {
+        let arg = arg("{name: n, type: int}");
+        assert!(accepts(&arg, "42"));
+        assert!(accepts(&arg, "-1"));
+        assert!(!accepts(&arg, "4.2"));
+        assert!(!accepts(&arg, "four"));
+        assert!(!accepts(&arg, ""));
+        assert_eq!(arg.expected().as_deref(), Some("an integer"));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn regex_values() {
+        let arg = arg("{name: version, regex: '\\d+\\.\\d+'}");
+        assert!(accepts(&arg, "1.2"));
+        assert!(!accepts(&arg, "v1.2"));
+        assert!(!accepts(&arg, "1.2.3"));
+        assert_eq!(arg.expected().as_deref(), Some("matching /\\d+\\.\\d+/"));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn regex_alternatives_are_anchored() {
+        let arg = arg("{name: target, regex: 'a|b'}");
+        assert!(accepts(&arg, "a"));
+        assert!(!accepts(&arg, "ab"));
+        assert!(!accepts(&arg, "xb"));
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[test]
+    fn regex_without_feature() {
+        assert!(arg("{name: version, regex: '\\d+'}").check_declaration().is_err());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn invalid_regex() {
+        let arg = arg("{name: x, regex: '('}");
+        assert!(arg.accepts("x", Path::new(".")).is_err());
+    }
+}
@@ -1,27 +1,90 @@
-use crate::{queue::JobState, JobRealization, ZinnError};
+use std::io::Write;
+#[cfg(feature = "progress")]
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(feature = "progress")]
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "progress")]
+use crate::history::{History, DEFAULT_ESTIMATE};
+use crate::{queue::{JobState, Summary}, JobRealization, ZinnError};
+
 
+/// Output stream of a job process
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// How output of parallel jobs is printed in verbose mode
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum OutputSync {
+    /// Print each line as soon as it arrives, prefixed with the job name
+    #[default]
+    Line,
+
+    /// Collect the output of a job and print it as one block when the job is done
+    Job,
+
+    /// Print output as it arrives without any prefix
+    None,
+}
 
 pub trait StateTracker {
     type ThreadStateTracker: ThreadStateTracker;
-    fn set_njobs(&self, njobs: usize);
+    fn set_jobs(&self, jobs: &[JobRealization]);
     fn start(&self);
     fn for_threads(&self, nthreads: usize) -> Vec<Self::ThreadStateTracker>;
+    fn finish(&self, summary: &Summary);
 }
 
 pub trait ThreadStateTracker: Send {
-    fn job_completed(&self, job: JobRealization, state: JobState, error: Option<ZinnError>);
+    fn job_started(&mut self, job: &JobRealization);
+    fn job_completed(&self, job: JobRealization, state: JobState, duration: Duration, error: Option<ZinnError>);
     fn start(&self);
     fn set_prefix(&mut self, prefix: String);
     fn clear_status(&mut self);
-    fn cmd_output(&mut self, out: &str, verbose: bool);
+    fn cmd_output(&mut self, out: &str, stream: OutputStream, verbose: bool);
     fn flush_cmd_output(&mut self, job: &str, verbose: bool);
     fn trace(&mut self, cmd: &str);
+
+    /// Show the progress of the current job (between 0 and 1) until the status is cleared
+    #[cfg_attr(not(feature = "regex"), allow(dead_code))]
+    fn set_progress(&mut self, progress: f64);
+
+    /// Give `f` exclusive access to the terminal, pausing the output of other threads
+    fn suspend<R>(&mut self, f: impl FnOnce() -> R) -> R;
 }
 
 #[cfg(feature = "progress")]
 pub struct Barkeeper {
     mp: indicatif::MultiProgress,
     bar: indicatif::ProgressBar,
+    output_sync: OutputSync,
+    eta: Arc<Mutex<Eta>>,
+}
+
+/// Number of steps of a job progress bar
+#[cfg(feature = "progress")]
+const PROGRESS_STEPS: u64 = 1000;
+
+
+/// Estimation of the remaining time based on the durations of previous runs
+#[cfg(feature = "progress")]
+struct Eta {
+    history: History,
+    nthreads: usize,
+
+    /// Estimated durations of jobs that have not been started yet
+    pending: HashMap<JobRealization, Duration>,
+
+    /// Start times of running jobs
+    running: HashMap<JobRealization, Instant>,
 }
 
 #[cfg(feature = "progress")]
@@ -30,27 +93,58 @@ pub struct ThreadBarkeeper {
     bar: indicatif::ProgressBar,
     main_bar: indicatif::ProgressBar,
     last_line: Option<String>,
+    output_sync: OutputSync,
+    buffer: Vec<String>,
+    eta: Arc<Mutex<Eta>>,
+
+    /// The bar currently shows the progress of a job instead of a spinner
+    has_progress: bool,
 }
 
-pub struct DummyBarkeeper {}
-pub struct DummyThreadBarkeeper { prefix: String }
+pub struct DummyBarkeeper { output_sync: OutputSync, stderr: bool, terminal: Arc<Mutex<()>> }
+pub struct DummyThreadBarkeeper { prefix: String, output_sync: OutputSync, stderr: bool, buffer: Vec<String>, terminal: Arc<Mutex<()>> }
 
 
 #[cfg(feature = "progress")]
 impl Barkeeper {
-    pub fn new() -> Self {
+    pub fn new(output_sync: OutputSync, history: History) -> Self {
         let mp = indicatif::MultiProgress::new();
-        let bar_style = indicatif::ProgressStyle::with_template("[{elapsed}] {wide_bar} {pos}/{len}").unwrap();
+        let bar_style = indicatif::ProgressStyle::with_template("[{elapsed}] {wide_bar} {pos}/{len} {msg}").unwrap();
         let bar = indicatif::ProgressBar::new(1);
         bar.set_style(bar_style);
 
-        Barkeeper { mp, bar }
+        let eta = Eta { history, nthreads: 1, pending: HashMap::new(), running: HashMap::new() };
+        Barkeeper { mp, bar, output_sync, eta: Arc::new(Mutex::new(eta)) }
+    }
+}
+
+#[cfg(feature = "progress")]
+impl Eta {
+    fn estimate(&self, job: &JobRealization) -> Duration {
+        self.history.estimate(&job.to_string()).unwrap_or(DEFAULT_ESTIMATE)
+    }
+
+    fn message(&self) -> String {
+        let now = Instant::now();
+        let pending: Duration = self.pending.values().sum();
+        let running: Duration = self.running.iter()
+            .map(|(job, start)| self.estimate(job).saturating_sub(now - *start))
+            .sum();
+        let remaining = (pending + running) / self.nthreads.max(1) as u32;
+
+        let mut msg = format!("ETA {}", indicatif::HumanDuration(remaining));
+        if let Some((job, start)) = self.running.iter().min_by_key(|(_, start)| **start) {
+            msg.push_str(&format!(" | longest: {} ({})", job, indicatif::HumanDuration(now - *start)));
+        }
+        msg
     }
 }
 
 impl DummyBarkeeper {
-    pub fn new() -> Self {
-        DummyBarkeeper {}
+    /// Create a tracker printing to stdout, or to stderr if `stderr` is set
+    /// (e.g. because stdout is used for machine-readable output)
+    pub fn new(output_sync: OutputSync, stderr: bool) -> Self {
+        DummyBarkeeper { output_sync, stderr, terminal: Arc::new(Mutex::new(())) }
     }
 }
 
@@ -58,8 +152,15 @@ impl DummyBarkeeper {
 impl StateTracker for Barkeeper {
     type ThreadStateTracker = ThreadBarkeeper;
 
-    fn set_njobs(&self, njobs: usize) {
-        self.bar.set_length(njobs as u64)
+    fn set_jobs(&self, jobs: &[JobRealization]) {
+        self.bar.set_length(jobs.len() as u64);
+
+        let mut eta = self.eta.lock().unwrap();
+        for job in jobs {
+            let estimate = eta.estimate(job);
+            eta.pending.insert(job.clone(), estimate);
+        }
+        self.bar.set_message(eta.message());
     }
 
     fn start(&self) {
@@ -69,45 +170,72 @@ impl StateTracker for Barkeeper {
     }
 
     fn for_threads(&self, nthreads: usize) -> Vec<ThreadBarkeeper> {
+        self.eta.lock().unwrap().nthreads = nthreads;
         (0..nthreads).map(|_| {
-            let bar = indicatif::ProgressBar::new(1);
-            bar.set_style(indicatif::ProgressStyle::with_template("{spinner} {prefix:.cyan} {wide_msg}").unwrap());
+            let bar = indicatif::ProgressBar::new(PROGRESS_STEPS);
+            bar.set_style(spinner_style());
 
             ThreadBarkeeper {
                 mp: self.mp.clone(),
                 main_bar: self.bar.clone(),
                 bar,
                 last_line: None,
+                output_sync: self.output_sync,
+                buffer: Vec::new(),
+                eta: self.eta.clone(),
+                has_progress: false,
             }
 
         }).collect()
     }
+
+    fn finish(&self, summary: &Summary) {
+        self.bar.set_message("");
+        self.bar.println(summary_msg(summary));
+    }
 }
 
 impl StateTracker for DummyBarkeeper {
     type ThreadStateTracker = DummyThreadBarkeeper;
 
-    fn set_njobs(&self, _njobs: usize) {}
+    fn set_jobs(&self, _jobs: &[JobRealization]) {}
 
     fn start(&self) {}
 
     fn for_threads(&self, nthreads: usize) -> Vec<DummyThreadBarkeeper> {
         (0..nthreads).map(|_| {
-            DummyThreadBarkeeper { prefix: String::new() }
+            DummyThreadBarkeeper {
+                prefix: String::new(),
+                output_sync: self.output_sync,
+                stderr: self.stderr,
+                buffer: Vec::new(),
+                terminal: self.terminal.clone(),
+            }
         }).collect()
     }
+
+    fn finish(&self, summary: &Summary) {
+        let _ = writeln!(human_output(self.stderr), "{}", summary_msg(summary));
+    }
 }
 
 impl ThreadStateTracker for DummyThreadBarkeeper {
-    fn job_completed(&self, job: JobRealization, state: JobState, error: Option<ZinnError>) {
-        println!("{}", job_finished_msg(job, state));
+    fn job_started(&mut self, _job: &JobRealization) {}
+
+    fn job_completed(&self, job: JobRealization, state: JobState, _duration: Duration, error: Option<ZinnError>) {
+        let _terminal = self.terminal.lock().unwrap();
+        let mut out = human_output(self.stderr);
+        let _ = writeln!(out, "{}", job_finished_msg(job, state));
         if let Some(e) = error {
-            if let ZinnError::ChildFailed(_, lines) = &e {
-                for line in lines {
-                    println!("{}: {}", self.prefix.to_owned(), line);
+            if let ZinnError::ChildFailed { tail, .. } = &e {
+                for line in tail {
+                    let _ = writeln!(out, "{}: {}", self.prefix, line);
                 }
             }
-            println!("{}", e);
+            let _ = writeln!(out, "{}", e);
+            if let ZinnError::ChildFailed { log: Some(log), .. } = &e {
+                let _ = writeln!(out, "{}", log_msg(log));
+            }
         }
     }
 
@@ -119,17 +247,39 @@ impl ThreadStateTracker for DummyThreadBarkeeper {
 
     fn clear_status(&mut self) {}
 
-    fn cmd_output(&mut self, out: &str, verbose: bool) {
-        let line = console::strip_ansi_codes(out).to_string();
+    fn cmd_output(&mut self, out: &str, stream: OutputStream, verbose: bool) {
+        let line = highlight_output(out, stream);
         if verbose {
-            println!("{}: {}", self.prefix, line);
+            let _terminal = self.terminal.lock().unwrap();
+            let _ = match self.output_sync {
+                OutputSync::Line => writeln!(human_output(self.stderr), "{}: {}", self.prefix, line),
+                OutputSync::Job => { self.buffer.push(format!("{}: {}", self.prefix, line)); Ok(()) },
+                OutputSync::None => writeln!(human_output(self.stderr), "{}", line),
+            };
         }
     }
 
-    fn flush_cmd_output(&mut self, _job: &str, _verbose: bool) {}
+    fn flush_cmd_output(&mut self, _job: &str, _verbose: bool) {
+        if !self.buffer.is_empty() {
+            // lock the output to keep the block together
+            let _terminal = self.terminal.lock().unwrap();
+            let mut out = human_output(self.stderr);
+            for line in self.buffer.drain(..) {
+                let _ = writeln!(out, "{}", line);
+            }
+        }
+    }
 
     fn trace(&mut self, cmd: &str) {
-        println!("{}", cmd);
+        let _terminal = self.terminal.lock().unwrap();
+        let _ = writeln!(human_output(self.stderr), "{}", cmd);
+    }
+
+    fn set_progress(&mut self, _progress: f64) {}
+
+    fn suspend<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        let _terminal = self.terminal.lock().unwrap();
+        f()
     }
 }
 
@@ -141,16 +291,31 @@ impl ThreadStateTracker for ThreadBarkeeper {
         self.bar.enable_steady_tick(std::time::Duration::from_millis(75));
     }
 
-    fn job_completed(&self, job: JobRealization, state: JobState, error: Option<ZinnError>) {
+    fn job_started(&mut self, job: &JobRealization) {
+        let mut eta = self.eta.lock().unwrap();
+        eta.pending.remove(job);
+        eta.running.insert(job.clone(), Instant::now());
+        self.main_bar.set_message(eta.message());
+    }
+
+    fn job_completed(&self, job: JobRealization, state: JobState, _duration: Duration, error: Option<ZinnError>) {
+        let mut eta = self.eta.lock().unwrap();
+        eta.running.remove(&job);
+        self.main_bar.set_message(eta.message());
+        drop(eta);
+
         self.bar.println(job_finished_msg(job, state));
         if let Some(e) = error {
-            if let ZinnError::ChildFailed(_, lines) = &e {
-                for line in lines {
+            if let ZinnError::ChildFailed { tail, .. } = &e {
+                for line in tail {
                     let prefix = self.bar.prefix();
                     self.bar.println(prefix + ": " + line);
                 }
             }
             self.bar.println(e.to_string());
+            if let ZinnError::ChildFailed { log: Some(log), .. } = &e {
+                self.bar.println(log_msg(log));
+            }
         }
         self.main_bar.inc(1)
     }
@@ -161,18 +326,30 @@ impl ThreadStateTracker for ThreadBarkeeper {
 
     fn clear_status(&mut self) {
         self.bar.set_message("");
+        if self.has_progress {
+            self.bar.set_style(spinner_style());
+            self.bar.set_position(0);
+            self.has_progress = false;
+        }
     }
 
-    fn cmd_output(&mut self, out: &str, verbose: bool) {
-        let line = console::strip_ansi_codes(out).to_string();
-        self.bar.set_message(line.to_owned());
+    fn cmd_output(&mut self, out: &str, stream: OutputStream, verbose: bool) {
+        self.bar.set_message(console::strip_ansi_codes(out).to_string());
+        self.main_bar.set_message(self.eta.lock().unwrap().message());
+        let line = highlight_output(out, stream);
 
         if verbose {
-            if let Some(line) = self.last_line.take() {
-                let prefix = self.bar.prefix();
-                self.bar.println(format!("{}: {}", prefix, line));
+            match self.output_sync {
+                OutputSync::Line => {
+                    if let Some(line) = self.last_line.take() {
+                        let prefix = self.bar.prefix();
+                        self.bar.println(format!("{}: {}", prefix, line));
+                    }
+                    self.last_line = Some(line);
+                },
+                OutputSync::Job => self.buffer.push(format!("{}: {}", self.bar.prefix(), line)),
+                OutputSync::None => self.bar.println(line),
             }
-            self.last_line = Some(line);
         }
     }
 
@@ -181,14 +358,52 @@ impl ThreadStateTracker for ThreadBarkeeper {
             if let Some(line) = self.last_line.take() {
                 self.bar.println(format!("{}: {}", job, line));
             }
+            if !self.buffer.is_empty() {
+                self.bar.println(self.buffer.join("\n"));
+                self.buffer.clear();
+            }
         }
     }
 
     fn trace(&mut self, cmd: &str) {
         self.bar.println(cmd);
     }
+
+    fn set_progress(&mut self, progress: f64) {
+        if !self.has_progress {
+            self.bar.set_style(progress_style());
+            self.has_progress = true;
+        }
+        self.bar.set_position((progress * PROGRESS_STEPS as f64) as u64);
+    }
+
+    fn suspend<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        // other bars block while trying to draw until the terminal is handed back
+        self.mp.suspend(f)
+    }
+}
+
+
+#[cfg(feature = "progress")]
+fn spinner_style() -> indicatif::ProgressStyle {
+    indicatif::ProgressStyle::with_template("{spinner} {prefix:.cyan} {wide_msg}").unwrap()
 }
 
+#[cfg(feature = "progress")]
+fn progress_style() -> indicatif::ProgressStyle {
+    indicatif::ProgressStyle::with_template("{spinner} {prefix:.cyan} [{bar:20}] {percent:>3}% {wide_msg}")
+        .unwrap()
+        .progress_chars("=> ")
+}
+
+/// Stream for human-readable output
+fn human_output(stderr: bool) -> Box<dyn Write> {
+    if stderr {
+        Box::new(std::io::stderr().lock())
+    } else {
+        Box::new(std::io::stdout().lock())
+    }
+}
 
 fn job_finished_msg(job: JobRealization, state: JobState ) -> String {
     match state {
@@ -198,3 +413,46 @@ fn job_finished_msg(job: JobRealization, state: JobState ) -> String {
         _ => panic!("Invalid job state after run: {:?}", state),
     }
 }
+
+/// Mark lines printed to stderr
+pub fn highlight_output(line: &str, stream: OutputStream) -> String {
+    match stream {
+        OutputStream::Stdout => line.to_owned(),
+        OutputStream::Stderr => console::style(line).yellow().to_string(),
+    }
+}
+
+fn log_msg(log: &std::path::Path) -> String {
+    format!("Full output in {}", log.display())
+}
+
+fn summary_msg(summary: &Summary) -> String {
+    let mut msg = format!("{} finished, {} skipped, {} failed, {} blocked, {} cancelled in {}",
+        summary.finished, summary.skipped, summary.failed, summary.blocked, summary.cancelled,
+        format_duration(summary.wall_time));
+    msg = if summary.failed > 0 {
+        console::style(msg).red().bold().to_string()
+    } else {
+        console::style(msg).green().bold().to_string()
+    };
+
+    if !summary.slowest.is_empty() {
+        msg.push_str("\nSlowest jobs:");
+        for (job, duration) in &summary.slowest {
+            msg.push_str(&format!("\n  {:>8} {}", format_duration(*duration), job));
+        }
+    }
+
+    if !summary.failed_jobs.is_empty() {
+        msg.push_str("\nFailed jobs:");
+        for job in &summary.failed_jobs {
+            msg.push_str(&format!("\n  {}", console::style(job).red()));
+        }
+    }
+
+    msg
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
+}
@@ -1,7 +1,32 @@
-struct ConstantVisitor(Vec<(String, String)>);
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::*;
+
+
+/// Value of a constant in the Zinnfile
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Constant {
+    /// Template that is rendered with all previous constants
+    Value(String),
+
+    /// Value of an environment variable (or a dotenv file)
+    ///
+    /// The default is rendered like a regular value if the variable is not set.
+    Env {
+        env: String,
+        #[serde(default)]
+        default: Option<String>,
+    },
+}
+
+struct ConstantVisitor(Vec<(String, Constant)>);
 
 impl<'de> serde::de::Visitor<'de> for ConstantVisitor {
-    type Value = Vec<(String, String)>;
+    type Value = Vec<(String, Constant)>;
     fn expecting(&self, _formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
         todo!("return nice descriptive error")
     }
@@ -17,9 +42,84 @@ where
     }
 }
 
-pub fn parse<'de, D>(des: D) -> Result<Vec<(String, String)>, D::Error>
+pub fn parse<'de, D>(des: D) -> Result<Vec<(String, Constant)>, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
     des.deserialize_map(ConstantVisitor(vec![]))
 }
+
+/// Read the key/value pairs of a dotenv file
+///
+/// Empty lines, comments and `export` prefixes are ignored, values may be quoted.
+pub fn load_dotenv(path: &Path) -> ZinnResult<Vec<(String, String)>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| ZinnError::Dotenv(path.display().to_string(), e))?;
+    parse_dotenv(&path.display().to_string(), &contents)
+}
+
+/// Parse the contents of the dotenv file `file`
+fn parse_dotenv(file: &str, contents: &str) -> ZinnResult<Vec<(String, String)>> {
+    let mut values = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => (key.trim(), value.trim()),
+            _ => return Err(ZinnError::DotenvLine(file.to_owned(), line.to_owned())),
+        };
+        let value = [('"', '"'), ('\'', '\'')].iter()
+            .find_map(|(open, close)| value.strip_prefix(*open)?.strip_suffix(*close))
+            .unwrap_or(value);
+        values.push((key.to_owned(), value.to_owned()));
+    }
+
+    Ok(values)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Vec<(String, String)> {
+        parse_dotenv(".env", contents).unwrap()
+    }
+
+    #[test]
+    fn dotenv_plain_values() {
+        assert_eq!(parse("A=1\nB = two words \n"), vec![
+            (String::from("A"), String::from("1")),
+            (String::from("B"), String::from("two words")),
+        ]);
+    }
+
+    #[test]
+    fn dotenv_quoting() {
+        assert_eq!(parse("A=\"x y\"\nB='z'\nC=\"unclosed\nD=\"\"\nE='mixed\""), vec![
+            (String::from("A"), String::from("x y")),
+            (String::from("B"), String::from("z")),
+            (String::from("C"), String::from("\"unclosed")),
+            (String::from("D"), String::new()),
+            (String::from("E"), String::from("'mixed\"")),
+        ]);
+    }
+
+    #[test]
+    fn dotenv_export_and_comments() {
+        let contents = "# comment\n\n  export A=1\n   # indented comment\nexport B=\"# not a comment\"\n";
+        assert_eq!(parse(contents), vec![
+            (String::from("A"), String::from("1")),
+            (String::from("B"), String::from("# not a comment")),
+        ]);
+    }
+
+    #[test]
+    fn dotenv_invalid_lines() {
+        assert!(matches!(parse_dotenv(".env", "A"), Err(ZinnError::DotenvLine(_, _))));
+        assert!(matches!(parse_dotenv(".env", " =1"), Err(ZinnError::DotenvLine(_, _))));
+    }
+}
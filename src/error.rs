@@ -1,4 +1,5 @@
 use std::io;
+use std::path::PathBuf;
 use std::process;
 use thiserror::Error;
 
@@ -12,8 +13,22 @@ pub enum ZinnError {
     #[error("{0}")]
     Yaml(#[from] serde_yaml::Error),
 
-    #[error("Child exited with error {0}")]
-    ChildFailed(i32, Vec<String>),
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Child exited with error {code}{}", step.as_ref().map(|step| format!(" in {}", step)).unwrap_or_default())]
+    ChildFailed {
+        code: i32,
+
+        /// Last lines of the output
+        tail: Vec<String>,
+
+        /// Log file with the full output
+        log: Option<PathBuf>,
+
+        /// Description of the failing step, if the job has more than one
+        step: Option<String>,
+    },
 
     #[error("Child terminated by signal")]
     ChildSignaled(),
@@ -27,6 +42,12 @@ pub enum ZinnError {
     #[error("Missing argument \"{0}\"")]
     MissingArgument(String),
 
+    #[error("Invalid value \"{2}\" for argument \"{1}\" of job \"{0}\" (expected {3})")]
+    InvalidArgument(String, String, String, String),
+
+    #[error("Invalid declaration of argument \"{1}\" of job \"{0}\" - {2}")]
+    InvalidArgDeclaration(String, String, String),
+
     #[error("Template rendering failed - ({0})")]
     RenderError(#[from] handlebars::RenderError),
 
@@ -39,6 +60,9 @@ pub enum ZinnError {
     #[error("Missing output file \"{0}\"")]
     OutputFileError(String),
 
+    #[error("Stdout redirection \"{0}\" is not declared as output")]
+    StdoutNotAnOutput(String),
+
     #[cfg(feature = "regex")]
     #[error("Unable to parse regex - {0}")]
     RegexError(#[from] regex_lite::Error),
@@ -48,6 +72,36 @@ pub enum ZinnError {
 
     #[error("Colon (':') in template name (\"{0}\")")]
     ColonInTemplateName(String),
+
+    #[error("Unable to include \"{0}\" - {1}")]
+    Include(String, Box<ZinnError>),
+
+    #[error("Unable to load Zinnfile \"{0}\" - {1}")]
+    Zinnfile(String, Box<ZinnError>),
+
+    #[error("Unable to read dotenv file \"{0}\" - {1}")]
+    Dotenv(String, io::Error),
+
+    #[error("Invalid line in dotenv file \"{0}\" ({1})")]
+    DotenvLine(String, String),
+
+    #[error("Environment variable \"{0}\" is not set and has no default")]
+    MissingEnvVar(String),
+
+    #[error("Include cycle detected (\"{0}\" includes itself)")]
+    IncludeCycle(String),
+
+    #[error("Job \"{0}\" is defined in both \"{1}\" and \"{2}\"")]
+    ConflictingJob(String, String, String),
+
+    #[error("Shell command line is empty")]
+    EmptyShell(),
+
+    #[error("Jobserver pipe closed unexpectedly")]
+    JobserverClosed(),
+
+    #[error("Working directory \"{0}\" does not exist")]
+    MissingDirectory(String),
 }
 
 pub fn die(e: impl Into<ZinnError>) -> ! {
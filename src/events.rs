@@ -0,0 +1,208 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::barkeeper::{OutputStream, StateTracker, ThreadStateTracker};
+use crate::error::*;
+use crate::queue::{JobState, Summary};
+use crate::JobRealization;
+
+
+type Sink = Option<Arc<Mutex<Box<dyn Write + Send>>>>;
+
+
+/// Tracker emitting newline-delimited JSON events in addition to another tracker
+///
+/// Without a sink all calls are just passed on to the inner tracker.
+pub struct EventTracker<T: StateTracker> {
+    inner: T,
+    sink: Sink,
+}
+
+pub struct ThreadEventTracker<T: ThreadStateTracker> {
+    inner: T,
+    sink: Sink,
+    job: Option<JobRealization>,
+}
+
+/// Line in the event stream
+#[derive(Serialize)]
+struct Record<'a> {
+    /// Seconds since the Unix epoch
+    time: f64,
+
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+enum Event<'a> {
+    GraphRealized {
+        jobs: Vec<JobInfo<'a>>,
+    },
+    JobStarted {
+        job: JobInfo<'a>,
+    },
+    Output {
+        job: Option<String>,
+        stream: OutputStream,
+        line: &'a str,
+    },
+    JobFinished {
+        job: JobInfo<'a>,
+        state: JobState,
+        duration: f64,
+        exit_code: Option<i32>,
+        error: Option<String>,
+    },
+    RunFinished {
+        finished: usize,
+        skipped: usize,
+        failed: usize,
+        blocked: usize,
+        cancelled: usize,
+        wall_time: f64,
+    },
+}
+
+#[derive(Serialize)]
+struct JobInfo<'a> {
+    /// Unique description of the job including its parameters
+    id: String,
+    name: &'a str,
+    params: &'a [String],
+    dependencies: Vec<String>,
+}
+
+
+impl<T: StateTracker> EventTracker<T> {
+    /// Create a tracker writing to `path` (`-` for stdout)
+    pub fn new(inner: T, path: Option<&str>) -> ZinnResult<Self> {
+        let writer: Box<dyn Write + Send> = match path {
+            None => return Ok(EventTracker { inner, sink: None }),
+            Some("-") => Box::new(io::stdout()),
+            Some(path) => Box::new(File::create(path)?),
+        };
+        Ok(EventTracker { inner, sink: Some(Arc::new(Mutex::new(writer))) })
+    }
+}
+
+impl<'a> JobInfo<'a> {
+    fn new(job: &'a JobRealization) -> Self {
+        JobInfo {
+            id: job.to_string(),
+            name: job.name(),
+            params: job.param_values(),
+            dependencies: job.dependencies().iter().map(|d| d.to_string()).collect(),
+        }
+    }
+}
+
+impl<T: StateTracker> StateTracker for EventTracker<T> {
+    type ThreadStateTracker = ThreadEventTracker<T::ThreadStateTracker>;
+
+    fn set_jobs(&self, jobs: &[JobRealization]) {
+        emit(&self.sink, Event::GraphRealized { jobs: jobs.iter().map(JobInfo::new).collect() });
+        self.inner.set_jobs(jobs);
+    }
+
+    fn start(&self) {
+        self.inner.start();
+    }
+
+    fn for_threads(&self, nthreads: usize) -> Vec<Self::ThreadStateTracker> {
+        self.inner.for_threads(nthreads).into_iter()
+            .map(|inner| ThreadEventTracker { inner, sink: self.sink.clone(), job: None })
+            .collect()
+    }
+
+    fn finish(&self, summary: &Summary) {
+        emit(&self.sink, Event::RunFinished {
+            finished: summary.finished,
+            skipped: summary.skipped,
+            failed: summary.failed,
+            blocked: summary.blocked,
+            cancelled: summary.cancelled,
+            wall_time: summary.wall_time.as_secs_f64(),
+        });
+        self.inner.finish(summary);
+    }
+}
+
+impl<T: ThreadStateTracker> ThreadStateTracker for ThreadEventTracker<T> {
+    fn job_started(&mut self, job: &JobRealization) {
+        emit(&self.sink, Event::JobStarted { job: JobInfo::new(job) });
+        self.job = Some(job.clone());
+        self.inner.job_started(job);
+    }
+
+    fn job_completed(&self, job: JobRealization, state: JobState, duration: Duration, error: Option<ZinnError>) {
+        let exit_code = match &error {
+            Some(ZinnError::ChildFailed { code, .. }) => Some(*code),
+            _ => None,
+        };
+        emit(&self.sink, Event::JobFinished {
+            job: JobInfo::new(&job),
+            state,
+            duration: duration.as_secs_f64(),
+            exit_code,
+            error: error.as_ref().map(|e| e.to_string()),
+        });
+        self.inner.job_completed(job, state, duration, error);
+    }
+
+    fn start(&self) {
+        self.inner.start();
+    }
+
+    fn set_prefix(&mut self, prefix: String) {
+        self.inner.set_prefix(prefix);
+    }
+
+    fn clear_status(&mut self) {
+        self.inner.clear_status();
+    }
+
+    fn cmd_output(&mut self, out: &str, stream: OutputStream, verbose: bool) {
+        if self.sink.is_some() {
+            let line = console::strip_ansi_codes(out);
+            emit(&self.sink, Event::Output { job: self.job.as_ref().map(|j| j.to_string()), stream, line: &line });
+        }
+        self.inner.cmd_output(out, stream, verbose);
+    }
+
+    fn flush_cmd_output(&mut self, job: &str, verbose: bool) {
+        self.inner.flush_cmd_output(job, verbose);
+    }
+
+    fn trace(&mut self, cmd: &str) {
+        self.inner.trace(cmd);
+    }
+
+    fn set_progress(&mut self, progress: f64) {
+        self.inner.set_progress(progress);
+    }
+
+    fn suspend<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        self.inner.suspend(f)
+    }
+}
+
+
+fn emit(sink: &Sink, event: Event) {
+    let sink = match sink {
+        Some(sink) => sink,
+        None => return,
+    };
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+    let record = Record { time, event };
+    if let Ok(json) = serde_json::to_string(&record) {
+        let mut sink = sink.lock().unwrap();
+        let _ = writeln!(sink, "{}", json);
+        let _ = sink.flush();
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::*;
+
+
+/// Directory for persistent state (relative to the Zinnfile)
+const STATE_DIR: &str = ".zinn";
+
+/// File in the state directory the history is stored in
+const HISTORY_FILE: &str = "history.yaml";
+
+/// Maximum number of durations saved per job
+const MAX_SAMPLES: usize = 10;
+
+/// Estimated duration for jobs without recorded history
+pub const DEFAULT_ESTIMATE: Duration = Duration::from_secs(1);
+
+
+/// Durations of jobs from previous runs
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    /// Durations in seconds, indexed by the job name including its parameters
+    jobs: BTreeMap<String, Vec<f64>>,
+
+    /// State directory the history is stored in
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+/// Statistics on previous runs of a job
+pub struct JobStats<'a> {
+    pub job: &'a str,
+    pub runs: usize,
+    pub avg: Duration,
+    pub max: Duration,
+
+    /// Relative change of the last run compared to the average of the runs before
+    pub trend: Option<f64>,
+}
+
+/// Directory for persistent state of the Zinnfile at `zinnfile` (relative to the Zinnfile)
+///
+/// Each Zinnfile has a subdirectory of its own, as several Zinnfiles may share a directory.
+pub fn state_dir(zinnfile: &Path) -> PathBuf {
+    Path::new(STATE_DIR).join(zinnfile.file_name().unwrap_or_default())
+}
+
+impl History {
+    /// Load the history from the state directory `dir`
+    ///
+    /// Returns an empty history if the file does not exist or cannot be parsed.
+    /// Samples that are not valid durations (e.g. negative ones) are dropped.
+    pub fn load(dir: PathBuf) -> Self {
+        let mut history: History = fs::read_to_string(dir.join(HISTORY_FILE)).ok()
+            .and_then(|contents| serde_yaml::from_str(&contents).ok())
+            .unwrap_or_default();
+        for samples in history.jobs.values_mut() {
+            samples.retain(|s| Duration::try_from_secs_f64(*s).is_ok());
+        }
+        history.dir = dir;
+        history
+    }
+
+    pub fn save(&self) -> ZinnResult<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.dir.join(HISTORY_FILE), serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn record(&mut self, job: &str, duration: Duration) {
+        let samples = self.jobs.entry(job.to_owned()).or_default();
+        samples.push(duration.as_secs_f64());
+        if samples.len() > MAX_SAMPLES {
+            samples.remove(0);
+        }
+    }
+
+    /// Average duration of previous runs of a job
+    pub fn estimate(&self, job: &str) -> Option<Duration> {
+        let samples = self.jobs.get(job)?;
+        if samples.is_empty() {
+            return None;
+        }
+        Some(Duration::from_secs_f64(average(samples)))
+    }
+
+    /// Statistics for all jobs, ordered by name
+    pub fn stats(&self) -> impl Iterator<Item = JobStats<'_>> {
+        self.jobs.iter()
+            .filter(|(_, samples)| !samples.is_empty())
+            .map(|(job, samples)| {
+                let max = samples.iter().cloned().fold(0.0, f64::max);
+                let (last, previous) = samples.split_last().unwrap();
+                let trend = if previous.is_empty() || average(previous) == 0.0 {
+                    None
+                } else {
+                    Some((last - average(previous)) / average(previous))
+                };
+
+                JobStats {
+                    job,
+                    runs: samples.len(),
+                    avg: Duration::from_secs_f64(average(samples)),
+                    max: Duration::from_secs_f64(max),
+                    trend,
+                }
+            })
+    }
+}
+
+fn average(samples: &[f64]) -> f64 {
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
@@ -1,31 +1,45 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::collections::VecDeque;
 use std::fmt;
 use std::fs;
-use std::path::Path;
-use std::process::Command;
-use std::io::{BufRead, BufReader};
-use std::sync::Arc;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::{mpsc, Arc};
+use std::thread;
 
-use handlebars::Handlebars;
 use serde::{Deserialize, Serialize};
 
-use crate::barkeeper::ThreadStateTracker;
+use crate::argument::ArgDeclaration;
+use crate::barkeeper::{highlight_output, OutputStream, ThreadStateTracker};
 use crate::error::*;
+use crate::pty::{self, Pty};
 use crate::queue::JobState;
-use crate::render_component;
+use crate::shell::{self, Shell};
+use crate::worker;
+use crate::workspace::{Scope, Workspace};
 use crate::Options;
 
-/// Maximum number of lines saved for a process to be printed on error.
-const MAX_SAVED_OUTPUT_LINES: usize = 20;
+
+/// Line printed by shell sessions before each step, followed by the index of the step
+const STEP_MARKER: &str = "\u{1e}zinn-step ";
 
 
 /// Template for a job as described in the Zinnfile
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct JobDescription {
     /// The shell commands to run for this job
+    ///
+    /// See also [`Run`].
     #[serde(default)]
-    run: String,
+    run: Run,
+
+    /// Whether the steps of `run` share a single shell session
+    ///
+    /// See also [`StepMode`].
+    #[serde(default, rename = "step-mode")]
+    step_mode: StepMode,
 
     /// Dependencies of the job
     ///
@@ -33,9 +47,15 @@ pub struct JobDescription {
     #[serde(default)]
     requires: Vec<JobDependency>,
 
+    /// Short description shown by `--list` and `zinn help`
+    #[serde(default)]
+    description: Option<String>,
+
     /// Argument declarations
+    ///
+    /// See also [`ArgDeclaration`].
     #[serde(default)]
-    args: Vec<String>,
+    args: Vec<ArgDeclaration>,
 
     /// Argument defaults
     #[serde(default)]
@@ -60,18 +80,96 @@ pub struct JobDescription {
     /// Run job in interactive mode
     #[serde(default)]
     interactive: bool,
+
+    /// Run job in a pseudo-terminal
+    ///
+    /// Some programs change their output if they are not attached to a terminal.
+    /// Stdout and stderr are not distinguished in this mode.
+    #[serde(default)]
+    tty: bool,
+
+    /// Interpreter for `run`, overriding the one of the Zinnfile
+    ///
+    /// See also [`Shell`].
+    #[serde(default)]
+    shell: Option<Shell>,
+
+    /// Directory to run the job in, relative to the Zinnfile
+    ///
+    /// Inputs, outputs and the stdout redirection are relative to this directory as well.
+    #[serde(default)]
+    dir: Option<String>,
+
+    /// Environment variables for the job
+    ///
+    /// They override variables of the same name set at the top level of the Zinnfile.
+    #[serde(default)]
+    env: HashMap<String, String>,
+
+    /// Start from an empty environment instead of the one Zinn was called with
+    #[serde(default, rename = "env-clear")]
+    env_clear: bool,
+
+    /// Redirect stdout of the job into this file
+    ///
+    /// The file must be one of the outputs of the job.
+    #[serde(default)]
+    stdout: Option<String>,
+
+    /// Regex to extract the progress of the job from its output
+    ///
+    /// The first capture group is parsed either as percentage or as `current/total`.
+    #[cfg(feature = "regex")]
+    #[serde(default, rename = "progress-regex")]
+    progress_regex: Option<String>,
 }
 
 /// Executable job with dependencies resolved and all variables applied
 #[derive(Clone, Debug, Hash, Eq, PartialEq)]
 pub struct InnerJobRealization {
     name: String,
-    run: String,
+    run: Vec<String>,
+    step_mode: StepMode,
+    shell: Vec<String>,
+    dir: PathBuf,
+    env: BTreeMap<String, String>,
+    env_clear: bool,
     interactive: bool,
+    tty: bool,
+    param_names: Vec<String>,
     param_values: Vec<String>,  // for info/debugging purposes
     dependencies: Vec<JobRealization>,
     inputs: Vec<String>,
     outputs: Vec<String>,
+    stdout: Option<String>,
+    #[cfg(feature = "regex")]
+    progress_regex: Option<String>,
+}
+
+/// Commands of a job, either as single script or as list of steps
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Run {
+    Script(String),
+    Steps(Vec<String>),
+}
+
+impl Default for Run {
+    fn default() -> Self {
+        Run::Script(String::new())
+    }
+}
+
+/// How the steps of a job are run
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepMode {
+    /// Each step runs in its own process
+    #[default]
+    Separate,
+
+    /// All steps run in the same shell process, so variables and the working directory are kept
+    Session,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -103,14 +201,20 @@ pub type JobRealization = Arc<InnerJobRealization>;
 
 impl JobDescription {
     /// Resolve templates and dependencies
-    pub fn realize(&self, name: &str, job_descriptions: &HashMap<String, JobDescription>, handlebars: &mut Handlebars, constants: &HashMap<String, String>, parameters: &HashMap<String, String>) -> ZinnResult<JobRealization> {
+    pub fn realize(&self, name: &str, scope: &Arc<Scope>, workspace: &mut Workspace, parameters: &HashMap<String, String>) -> ZinnResult<JobRealization> {
         let mut dependencies = Vec::new();
         let mut param_values = Vec::new();
         let name = name.to_owned();
 
-        let mut combined_vars = constants.clone();
+        let mut combined_vars = scope.constants().clone();
 
         for arg in &self.args {
+            if let Err(reason) = arg.check_declaration() {
+                return Err(ZinnError::InvalidArgDeclaration(scope.job_name(&name), arg.name().to_owned(), reason.to_owned()));
+            }
+        }
+
+        for arg in self.args.iter().map(ArgDeclaration::name) {
             match parameters.get(arg).or(self.defaults.get(arg)) {
                 Some(val) => {
                     combined_vars.insert(arg.to_owned(), val.to_owned());
@@ -120,11 +224,27 @@ impl JobDescription {
             }
         }
 
+        let dir = match &self.dir {
+            Some(dir) => {
+                let template_path = ["jobs", &name, "dir"];
+                scope.dir().join(workspace.render(scope, &template_path, dir, &combined_vars)?)
+            },
+            None => scope.dir().to_owned(),
+        };
+
+        // check the values against the declarations
+        for (arg, value) in self.args.iter().zip(&param_values) {
+            if !arg.accepts(value, &dir)? {
+                return Err(ZinnError::InvalidArgument(scope.job_name(&name), arg.name().to_owned(),
+                    value.to_owned(), arg.expected().unwrap_or_default()));
+            }
+        }
+
         // render input files
         let mut inputs = Vec::new();
         if let Some(input_str) = &self.inputs {
             let template_path = ["jobs", &name, "inputs"];
-            let rendered_input_str = render_component(&template_path, input_str, handlebars, &combined_vars)?;
+            let rendered_input_str = workspace.render(scope, &template_path, input_str, &combined_vars)?;
             let additional_inputs = rendered_input_str.split(char::is_whitespace)
                 .filter(|v| !v.is_empty())
                 .map(|s| s.to_owned());
@@ -132,7 +252,7 @@ impl JobDescription {
         }
         for (i, input) in self.input_list.iter().enumerate() {
             let template_path = ["jobs", &name, "input-list", &i.to_string()];
-            let rendered = render_component(&template_path, input, handlebars, &combined_vars)?;
+            let rendered = workspace.render(scope, &template_path, input, &combined_vars)?;
             inputs.push(rendered);
         }
 
@@ -140,7 +260,7 @@ impl JobDescription {
         let mut outputs = Vec::new();
         if let Some(output_str) = &self.outputs {
             let template_path = ["jobs", &name, "outputs"];
-            let rendered_output_str = render_component(&template_path, output_str, handlebars, &combined_vars)?;
+            let rendered_output_str = workspace.render(scope, &template_path, output_str, &combined_vars)?;
             let additional_outputs = rendered_output_str .split(char::is_whitespace)
                 .filter(|v| !v.is_empty())
                 .map(|s| s.to_owned());
@@ -148,7 +268,7 @@ impl JobDescription {
         }
         for (i, output) in self.output_list.iter().enumerate() {
             let template_path = ["jobs", &name, "output-list", &i.to_string()];
-            let rendered = render_component(&template_path, output, handlebars, &combined_vars)?;
+            let rendered = workspace.render(scope, &template_path, output, &combined_vars)?;
             outputs.push(rendered);
         }
 
@@ -156,46 +276,120 @@ impl JobDescription {
             let mut realized_dep_params = dep.with.clone();
             for (key, val) in &mut realized_dep_params {
                 let template_path = ["jobs", &name, "requires", &i.to_string(), key];
-                *val = render_component(&template_path, val, handlebars, &combined_vars)?;
+                *val = workspace.render(scope, &template_path, val, &combined_vars)?;
             }
 
-            let dep_desc = match job_descriptions.get(&dep.job) {
+            // the dependency may be part of another Zinnfile
+            let (dep_scope, dep_name) = workspace.lookup(scope, &dep.job)?;
+            let dep_desc = match dep_scope.jobs().get(&dep_name) {
                 Some(desc) => desc,
                 None => return Err(ZinnError::DependencyNotFound(dep.job.to_owned())),
             };
 
             if let Some(with_list) = &dep.foreach {
                 let template_path = ["jobs", &name, "requires", &i.to_string(), "foreach"];
-                let inputs = render_component(&template_path, &with_list.r#in, handlebars, &combined_vars)?;
+                let inputs = workspace.render(scope, &template_path, &with_list.r#in, &combined_vars)?;
                 let val_list = inputs.split(char::is_whitespace)
                     .filter(|v| !v.is_empty());
                 for val in val_list {
                     // mutating the environment is fine, as it will be overridden
                     // for every iteration with the proper value.
                     realized_dep_params.insert(with_list.var.to_owned(), val.to_owned());
-                    let dep_realization = dep_desc.realize(&dep.job, job_descriptions, handlebars, constants, &realized_dep_params)?;
+                    let dep_realization = dep_desc.realize(&dep_name, &dep_scope, workspace, &realized_dep_params)?;
                     dependencies.push(dep_realization);
                 }
             } else {
-                let dep_realization = dep_desc.realize(&dep.job, job_descriptions, handlebars, constants, &realized_dep_params)?;
+                let dep_realization = dep_desc.realize(&dep_name, &dep_scope, workspace, &realized_dep_params)?;
                 dependencies.push(dep_realization);
             }
         }
 
-        let template_path = ["jobs", &name, "run"];
-        let run = render_component(&template_path, &self.run, handlebars, &combined_vars)?;
-        let name = name.replace('\n', "");
+        let stdout = match &self.stdout {
+            Some(file) => {
+                let template_path = ["jobs", &name, "stdout"];
+                let rendered = workspace.render(scope, &template_path, file, &combined_vars)?;
+                if !outputs.contains(&rendered) {
+                    return Err(ZinnError::StdoutNotAnOutput(rendered));
+                }
+                Some(rendered)
+            },
+            None => None,
+        };
+
+        let run = match &self.run {
+            Run::Script(script) => {
+                let template_path = ["jobs", &name, "run"];
+                vec![workspace.render(scope, &template_path, script, &combined_vars)?]
+            },
+            Run::Steps(steps) => steps.iter().enumerate()
+                .map(|(i, step)| {
+                    let template_path = ["jobs", &name, "run", &i.to_string()];
+                    workspace.render(scope, &template_path, step, &combined_vars)
+                })
+                .collect::<ZinnResult<_>>()?,
+        };
+        let step_mode = self.step_mode;
+
+        // render environment variables, job specific ones take precedence
+        let mut env = BTreeMap::new();
+        for (key, val) in scope.env() {
+            let template_path = ["env", key];
+            env.insert(key.to_owned(), workspace.render(scope, &template_path, val, &combined_vars)?);
+        }
+        for (key, val) in &self.env {
+            let template_path = ["jobs", &name, "env", key];
+            env.insert(key.to_owned(), workspace.render(scope, &template_path, val, &combined_vars)?);
+        }
+        let env_clear = self.env_clear;
+        let shell = self.shell.as_ref().or(scope.shell()).cloned().unwrap_or_default().argv()?;
+
+        let name = scope.job_name(&name).replace('\n', "");
         let interactive = self.interactive;
+        let tty = self.tty;
+
+        // check the regex early instead of failing once the job runs
+        #[cfg(feature = "regex")]
+        let progress_regex = match &self.progress_regex {
+            Some(re) => { regex_lite::Regex::new(re)?; Some(re.to_owned()) },
+            None => None,
+        };
 
         Ok(Arc::new(InnerJobRealization {
-            name, run, dependencies, inputs, outputs, param_values, interactive
+            name, run, step_mode, shell, dir, env, env_clear, dependencies, inputs, outputs, interactive, tty, stdout,
+            param_names: self.args.iter().map(|a| a.name().to_owned()).collect(),
+            param_values,
+            #[cfg(feature = "regex")]
+            progress_regex,
         }))
     }
 
-    pub fn args(&self) -> &Vec<String> {
+    pub fn args(&self) -> &Vec<ArgDeclaration> {
         &self.args
     }
 
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Default value of an argument
+    pub fn default(&self, arg: &str) -> Option<&str> {
+        self.defaults.get(arg).map(String::as_str)
+    }
+
+    /// Names of the jobs this job depends on
+    pub fn dependency_names(&self) -> Vec<&str> {
+        self.requires.iter().map(|d| d.job.as_str()).collect()
+    }
+
+    /// Move dependencies on any of `local_jobs` into `namespace`
+    pub fn add_namespace(&mut self, namespace: &str, local_jobs: &HashSet<String>) {
+        for dep in &mut self.requires {
+            if local_jobs.contains(&dep.job) {
+                dep.job = format!("{}.{}", namespace, dep.job);
+            }
+        }
+    }
+
 }
 
 impl InnerJobRealization {
@@ -203,11 +397,18 @@ impl InnerJobRealization {
         // skip if dry run
         if options.dry_run {
             if options.trace {
-                tracker.trace(self.cmd());
+                for step in &self.run {
+                    tracker.trace(step);
+                }
             }
             return Ok(JobState::Finished);
         }
 
+        // check the working directory, as spawning the shell would only report a missing file
+        if !self.dir.is_dir() {
+            return Err(ZinnError::MissingDirectory(self.dir.display().to_string()));
+        }
+
         // check if all input files exist
         self.check_input_files()?;
 
@@ -216,66 +417,248 @@ impl InnerJobRealization {
             return Ok(JobState::Skipped);
         }
 
-        // print out trace
-        if options.trace {
-            tracker.trace(self.cmd());
+        // the full output is kept in a log file
+        let mut log = None;
+        if !self.interactive {
+            let path = self.log_path(options);
+            fs::create_dir_all(&options.log_dir)?;
+            let mut writer = BufWriter::new(fs::File::create(&path)?);
+            if let Some(file) = &self.stdout {
+                writeln!(writer, "[stdout redirected to {}]", file)?;
+            }
+            log = Some((path, writer));
         }
 
+        let result = self.run_steps(tracker, options, &mut log);
+        // flushed only once, so the output of all steps is kept together
+        tracker.flush_cmd_output(&self.to_string(), options.verbose);
+        result?;
+
+        for file in &self.outputs {
+            if !self.dir.join(file).exists() {
+                return Err(ZinnError::OutputFileError(file.to_owned()));
+            }
+        }
+
+        Ok(JobState::Finished)
+    }
+
+    /// Run all steps, stopping at the first failing one
+    fn run_steps(&self, tracker: &mut impl ThreadStateTracker, options: &Options,
+                 log: &mut Option<(PathBuf, BufWriter<fs::File>)>) -> ZinnResult<()> {
         // in order to being able to discard the oldest lines a VecDeque is used to track output
         // lines and reversed before returning the last N lines the caller
         let mut out_lines = VecDeque::new();
 
-        let cmd_with_exit_setting = format!("set -e; {}", self.run);
-        let mut  process = if self.interactive {
-            // run job interactively
-            Command::new("sh")
-                .arg("-c")
-                .arg(&cmd_with_exit_setting)
-                .spawn()?
-        } else {
-            // run job without user interaction and track output
-            let (io_reader, io_writer) = os_pipe::pipe()?;
-            let process = Command::new("sh")
-                .arg("-c")
-                .arg(&cmd_with_exit_setting)
-                .stdout(io_writer.try_clone()?)
-                .stderr(io_writer)
-                .spawn()?;
-
-            for line in BufReader::new(io_reader).lines().map_while(Result::ok) {
-                tracker.cmd_output(&line, options.verbose);
-
-                // append line to limited output buffer
-                out_lines.push_front(line);
-                out_lines.truncate(MAX_SAVED_OUTPUT_LINES);
+        // steps of a session share a single process
+        let batches: Vec<_> = match self.step_mode {
+            StepMode::Session => vec![(0, &self.run[..])],
+            StepMode::Separate => self.run.chunks(1).enumerate().collect(),
+        };
+
+        for (first_step, steps) in batches {
+            let mut step = first_step;
+
+            // markers in the output tell which step of a session is running
+            // (they are printed to stdout if possible to keep them in order with most of the output)
+            let markers = steps.len() > 1 && !self.interactive && shell::is_posix(&self.shell);
+            let script = if markers {
+                let redirect = if self.stdout.is_some() { " >&2" } else { "" };
+                steps.iter().enumerate()
+                    .map(|(i, s)| format!("printf '{}%d\\n' {}{}\n{}", STEP_MARKER, first_step + i, redirect, s))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            } else if steps.len() == 1 {
+                self.start_step(tracker, options, first_step);
+                steps[0].clone()
+            } else {
+                if options.trace {
+                    for step in steps {
+                        tracker.trace(step);
+                    }
+                }
+                steps.join("\n")
+            };
+
+            let mut command = shell::command(&self.shell, &script);
+            command.current_dir(&self.dir);
+            if self.env_clear {
+                command.env_clear();
+            }
+            command.envs(self.zinn_env(options));
+            command.envs(&self.env);
+            if let Some(file) = &self.stdout {
+                // later steps append to the output of previous ones
+                let file = fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .append(first_step > 0)
+                    .truncate(first_step == 0)
+                    .open(self.dir.join(file))?;
+                command.stdout(file);
             }
-            tracker.flush_cmd_output(&self.to_string(), options.verbose);
 
-            process
-        };
+            let status = match log {
+                // run job interactively with exclusive access to the terminal
+                None => tracker.suspend(|| command.status())?,
 
-        let out_lines = out_lines.into_iter().rev().collect();
+                // run job without user interaction and track output
+                Some((_, log)) => self.run_tracked(command, tracker, options, log, &mut out_lines, &mut step)?,
+            };
 
-        let status = process.wait()?;
-        if !status.success() {
-            match status.code() {
-                Some(code) => return Err(ZinnError::ChildFailed(code, out_lines)),
-                None => return Err(ZinnError::ChildSignaled()),
+            if !status.success() {
+                let tail = out_lines.into_iter().rev().collect();
+                let log = log.as_ref().map(|(path, _)| path.clone());
+                let step = (self.run.len() > 1).then(|| self.step_label(step));
+                match status.code() {
+                    Some(code) => return Err(ZinnError::ChildFailed { code, tail, log, step }),
+                    None => return Err(ZinnError::ChildSignaled()),
+                }
             }
         }
 
-        for file in &self.outputs {
-            if !Path::new(file).exists() {
-                return Err(ZinnError::OutputFileError(file.to_owned()));
+        Ok(())
+    }
+
+    /// Run a non-interactive process, forwarding its output to the tracker and the log file
+    ///
+    /// `step` is updated with the step markers printed by the process.
+    fn run_tracked(&self, mut command: Command, tracker: &mut impl ThreadStateTracker, options: &Options,
+                   log: &mut BufWriter<fs::File>, out_lines: &mut VecDeque<String>, step: &mut usize) -> ZinnResult<ExitStatus> {
+        if options.keep_colors {
+            // convince the child to emit colors, even though its output is a pipe
+            command.env("CLICOLOR_FORCE", "1").env("FORCE_COLOR", "1");
+        }
+
+        let mut readers: Vec<(Box<dyn Read + Send>, OutputStream)> = Vec::new();
+        if self.tty {
+            // stdout and stderr cannot be told apart on a terminal
+            let pty = Pty::open()?;
+            if self.stdout.is_none() {
+                command.stdout(pty.slave.try_clone()?);
+            }
+            command.stderr(pty.slave).stdin(Stdio::null());
+            pty::set_controlling_terminal(&mut command);
+            readers.push((Box::new(pty.master), OutputStream::Stdout));
+        } else {
+            let (out_reader, out_writer) = os_pipe::pipe()?;
+            let (err_reader, err_writer) = os_pipe::pipe()?;
+            if self.stdout.is_none() {
+                command.stdout(out_writer);
+                readers.push((Box::new(out_reader), OutputStream::Stdout));
             }
+            command.stderr(err_writer);
+            readers.push((Box::new(err_reader), OutputStream::Stderr));
         }
 
-        Ok(JobState::Finished)
+        let mut process = command.spawn()?;
+        // close our copies of the write ends, so the readers terminate with the process
+        drop(command);
+
+        #[cfg(feature = "regex")]
+        let progress_regex = self.progress_regex.as_deref().map(regex_lite::Regex::new).transpose()?;
+
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| -> ZinnResult<()> {
+            for (reader, stream) in readers {
+                let sender = sender.clone();
+                scope.spawn(move || forward_lines(reader, stream, sender));
+            }
+            drop(sender);
+
+            for (stream, line) in receiver {
+                // a step not ending with a newline is followed by the marker of the next step on the same line
+                let (line, next_step) = match split_step_marker(&line) {
+                    Some((output, next)) => (output.to_owned(), Some(next)),
+                    None => (line, None),
+                };
+
+                if next_step.is_none() || !line.is_empty() {
+                    let line = if options.keep_colors {
+                        line
+                    } else {
+                        console::strip_ansi_codes(&line).to_string()
+                    };
+
+                    let plain_line = console::strip_ansi_codes(&line);
+                    #[cfg(feature = "regex")]
+                    if let Some(progress) = progress_regex.as_ref().and_then(|re| parse_progress(re, &plain_line)) {
+                        tracker.set_progress(progress);
+                    }
+                    tracker.cmd_output(&line, stream, options.verbose);
+
+                    match stream {
+                        OutputStream::Stdout => writeln!(log, "{}", plain_line)?,
+                        OutputStream::Stderr => writeln!(log, "[stderr] {}", plain_line)?,
+                    }
+
+                    // append line to limited output buffer
+                    out_lines.push_front(highlight_output(&line, stream));
+                    out_lines.truncate(options.tail);
+                }
+
+                if let Some(next) = next_step {
+                    *step = next;
+                    self.start_step(tracker, options, next);
+                }
+            }
+
+            Ok(())
+        })?;
+        log.flush()?;
+
+        Ok(process.wait()?)
+    }
+
+    /// Trace a step and show it in the prefix of the job
+    fn start_step(&self, tracker: &mut impl ThreadStateTracker, options: &Options, step: usize) {
+        if self.run.len() > 1 {
+            tracker.set_prefix(format!("{} step {}/{}", worker::job_prefix(self), step + 1, self.run.len()));
+        }
+        if options.trace {
+            if let Some(cmd) = self.run.get(step) {
+                tracker.trace(cmd);
+            }
+        }
+    }
+
+    /// Description of a step for error messages
+    fn step_label(&self, step: usize) -> String {
+        let cmd = self.run.get(step)
+            .and_then(|cmd| cmd.lines().find(|l| !l.trim().is_empty()))
+            .unwrap_or_default()
+            .trim();
+        format!("step {}/{} ({})", step + 1, self.run.len(), cmd)
+    }
+
+    /// Variables describing the job to the processes it runs
+    fn zinn_env(&self, options: &Options) -> Vec<(String, String)> {
+        let mut env = vec![
+            (String::from("ZINN_JOB"), self.name.clone()),
+            (String::from("ZINN_INPUTS"), self.inputs.join(" ")),
+            (String::from("ZINN_OUTPUTS"), self.outputs.join(" ")),
+            (String::from("ZINN_JOBS"), options.jobs.to_string()),
+        ];
+        for (name, value) in self.param_names.iter().zip(&self.param_values) {
+            let name: String = name.chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            env.push((format!("ZINN_PARAM_{}", name), value.clone()));
+        }
+        env
+    }
+
+    /// Location of the log file containing the full output of the job
+    fn log_path(&self, options: &Options) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        self.param_values.hash(&mut hasher);
+        let file_name = format!("{}-{:016x}.log", self.name.replace('/', "_"), hasher.finish());
+        Path::new(&options.log_dir).join(file_name)
     }
 
     fn check_input_files(&self) -> ZinnResult<()> {
         for file in &self.inputs {
-            if !Path::new(file).exists() {
+            if !self.dir.join(file).exists() {
                 return Err(ZinnError::InputFileError(file.to_owned()));
             }
         }
@@ -286,13 +669,14 @@ impl InnerJobRealization {
     fn check_file_skip(&self) -> ZinnResult<bool> {
         self.check_input_files()?;
         for output in &self.outputs {
-            if !Path::new(output).exists() {
+            let output = self.dir.join(output);
+            if !output.exists() {
                 return Ok(false);
             }
 
             for input in &self.inputs {
-                let out_time = fs::metadata(output)?.modified()?;
-                let in_time = fs::metadata(input)?.modified()?;
+                let out_time = fs::metadata(&output)?.modified()?;
+                let in_time = fs::metadata(self.dir.join(input))?.modified()?;
                 if in_time > out_time {
                     return Ok(false);
                 }
@@ -316,16 +700,49 @@ impl InnerJobRealization {
         &self.name
     }
 
-    pub fn cmd(&self) -> &str {
+    pub fn param_values(&self) -> &[String] {
+        &self.param_values
+    }
+
+    pub fn steps(&self) -> &[String] {
         &self.run
     }
+}
 
-    #[cfg(feature = "progress")]
-    pub fn is_interactive(&self) -> bool {
-        self.interactive
+/// Send lines read from a child process to the thread tracking its output
+fn forward_lines(reader: impl Read, stream: OutputStream, sender: mpsc::Sender<(OutputStream, String)>) {
+    for line in BufReader::new(reader).lines().map_while(Result::ok) {
+        // only keep the last state of lines that are redrawn with carriage returns
+        let line = line.trim_end_matches('\r')
+            .rsplit('\r')
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+        if sender.send((stream, line)).is_err() {
+            break;
+        }
     }
 }
 
+/// Split an output line into the output preceding a step marker and the index of the next step
+fn split_step_marker(line: &str) -> Option<(&str, usize)> {
+    let (output, marker) = line.split_once(STEP_MARKER)?;
+    Some((output, marker.trim().parse().ok()?))
+}
+
+/// Extract the progress (between 0 and 1) from an output line
+///
+/// The first capture group is either a percentage or of the form `current/total`.
+#[cfg(feature = "regex")]
+fn parse_progress(re: &regex_lite::Regex, line: &str) -> Option<f64> {
+    let capture = re.captures(line)?.get(1)?.as_str().trim();
+    let progress = match capture.split_once('/') {
+        Some((current, total)) => current.trim().parse::<f64>().ok()? / total.trim().parse::<f64>().ok()?,
+        None => capture.trim_end_matches('%').trim().parse::<f64>().ok()? / 100.0,
+    };
+    progress.is_finite().then(|| progress.clamp(0.0, 1.0))
+}
+
 
 impl fmt::Display for InnerJobRealization {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -341,3 +758,68 @@ impl fmt::Display for InnerJobRealization {
         Ok(())
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "regex")]
+    fn progress(line: &str) -> Option<f64> {
+        parse_progress(&regex_lite::Regex::new(r"\[(.*)\]").unwrap(), line)
+    }
+
+    #[test]
+    fn step_marker_on_own_line() {
+        assert_eq!(split_step_marker(&format!("{}2", STEP_MARKER)), Some(("", 2)));
+        assert_eq!(split_step_marker("regular output"), None);
+    }
+
+    #[test]
+    fn step_marker_after_output_without_newline() {
+        assert_eq!(split_step_marker(&format!("no newline{}1", STEP_MARKER)), Some(("no newline", 1)));
+    }
+
+    #[test]
+    fn step_marker_without_index() {
+        assert_eq!(split_step_marker(&format!("output{}", STEP_MARKER)), None);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn progress_percentage() {
+        assert_eq!(progress("download [42%]"), Some(0.42));
+        assert_eq!(progress("download [ 100 % ]"), Some(1.0));
+        assert_eq!(progress("download [7.5]"), Some(0.075));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn progress_fraction() {
+        assert_eq!(progress("step [3/4]"), Some(0.75));
+        assert_eq!(progress("step [ 1 / 2 ]"), Some(0.5));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn progress_clamped() {
+        assert_eq!(progress("[150%]"), Some(1.0));
+        assert_eq!(progress("[-1/2]"), Some(0.0));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn progress_division_by_zero() {
+        assert_eq!(progress("[3/0]"), None);
+        assert_eq!(progress("[0/0]"), None);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn progress_no_match() {
+        assert_eq!(progress("no brackets"), None);
+        assert_eq!(progress("[abc]"), None);
+        assert_eq!(progress("[1/x]"), None);
+        assert_eq!(parse_progress(&regex_lite::Regex::new(r"\d+%").unwrap(), "42%"), None);
+    }
+}
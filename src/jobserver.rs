@@ -0,0 +1,166 @@
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::{FromRawFd, RawFd};
+use std::sync::Mutex;
+
+use crate::error::*;
+
+
+/// Character used for tokens created by Zinn
+const TOKEN: u8 = b'+';
+
+
+/// Jobserver compatible with the GNU make protocol
+///
+/// Every running job needs a token.
+/// The first token is implicit, all others are read from a pipe that is shared with child
+/// processes (e.g. `make` or `cargo`) through the `MAKEFLAGS` environment variable.
+pub struct Jobserver {
+    reader: File,
+    writer: File,
+    auth: String,
+    njobs: Option<usize>,
+    state: Mutex<TokenState>,
+}
+
+/// Bookkeeping for the implicit token
+struct TokenState {
+    /// The implicit token is not held by any job
+    implicit_free: bool,
+
+    /// The implicit token was written to the pipe to wake up a waiting worker
+    implicit_lent: bool,
+
+    /// Number of workers blocked while reading from the pipe
+    waiting: usize,
+}
+
+/// Token held by a running job
+///
+/// The token is given back to the jobserver when dropped.
+pub struct Token<'a> {
+    jobserver: &'a Jobserver,
+    byte: Option<u8>,
+}
+
+
+impl Jobserver {
+    /// Create a new jobserver with `njobs` tokens (including the implicit one)
+    pub fn new(njobs: usize) -> ZinnResult<Self> {
+        let mut fds: [RawFd; 2] = [0; 2];
+        // the fds are deliberately created without CLOEXEC, so child processes inherit them
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let reader = unsafe { File::from_raw_fd(fds[0]) };
+        let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+
+        for _ in 1..njobs {
+            writer.write_all(&[TOKEN])?;
+        }
+
+        let auth = format!("{},{}", fds[0], fds[1]);
+        Ok(Self::with_files(reader, writer, auth, Some(njobs)))
+    }
+
+    /// Connect to a jobserver inherited through `MAKEFLAGS`
+    ///
+    /// Returns [`None`] if there is no jobserver or its file descriptors are not accessible.
+    pub fn from_env() -> Option<Self> {
+        let makeflags = env::var("MAKEFLAGS").ok()?;
+        let auth = makeflags.split_whitespace()
+            .filter_map(|f| f.strip_prefix("--jobserver-auth=").or(f.strip_prefix("--jobserver-fds=")))
+            .next_back()?
+            .to_owned();
+        let njobs = makeflags.split_whitespace()
+            .filter_map(|f| f.strip_prefix("-j")?.parse().ok())
+            .next_back();
+
+        if let Some(path) = auth.strip_prefix("fifo:") {
+            let reader = OpenOptions::new().read(true).open(path).ok()?;
+            let writer = OpenOptions::new().write(true).open(path).ok()?;
+            return Some(Self::with_files(reader, writer, auth, njobs));
+        }
+
+        let (read_fd, write_fd) = auth.split_once(',')?;
+        let read_fd: RawFd = read_fd.parse().ok()?;
+        let write_fd: RawFd = write_fd.parse().ok()?;
+        if read_fd < 0 || write_fd < 0 || !fd_valid(read_fd) || !fd_valid(write_fd) {
+            return None;
+        }
+
+        let reader = unsafe { File::from_raw_fd(read_fd) };
+        let writer = unsafe { File::from_raw_fd(write_fd) };
+        Some(Self::with_files(reader, writer, auth, njobs))
+    }
+
+    fn with_files(reader: File, writer: File, auth: String, njobs: Option<usize>) -> Self {
+        let state = TokenState { implicit_free: true, implicit_lent: false, waiting: 0 };
+        Jobserver { reader, writer, auth, njobs, state: Mutex::new(state) }
+    }
+
+    /// Total number of tokens, if known
+    pub fn njobs(&self) -> Option<usize> {
+        self.njobs
+    }
+
+    /// Value for `MAKEFLAGS` that lets child processes join this jobserver
+    pub fn makeflags(&self, njobs: usize) -> String {
+        format!("-j{} --jobserver-auth={}", njobs, self.auth)
+    }
+
+    /// Wait for a token to become available
+    pub fn acquire(&self) -> ZinnResult<Token<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if state.implicit_free {
+            state.implicit_free = false;
+            return Ok(Token { jobserver: self, byte: None });
+        }
+        state.waiting += 1;
+        drop(state);
+
+        let mut byte = [0];
+        let result = loop {
+            match (&self.reader).read(&mut byte) {
+                Ok(1) => break Ok(Token { jobserver: self, byte: Some(byte[0]) }),
+                Ok(_) => break Err(ZinnError::JobserverClosed()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => break Err(e.into()),
+            }
+        };
+
+        self.state.lock().unwrap().waiting -= 1;
+        result
+    }
+
+    fn release(&self, byte: Option<u8>) {
+        let mut state = self.state.lock().unwrap();
+        let write = match byte {
+            // hand the implicit token to a blocked worker through the pipe
+            None if state.waiting > 0 => { state.implicit_lent = true; Some(TOKEN) },
+            None => { state.implicit_free = true; None },
+            // take back the implicit token instead of returning this one
+            Some(_) if state.implicit_lent && state.waiting == 0 => {
+                state.implicit_lent = false;
+                state.implicit_free = true;
+                None
+            },
+            Some(b) => Some(b),
+        };
+
+        if let Some(b) = write {
+            let _ = (&self.writer).write_all(&[b]);
+        }
+    }
+}
+
+impl Drop for Token<'_> {
+    fn drop(&mut self) {
+        self.jobserver.release(self.byte);
+    }
+}
+
+fn fd_valid(fd: RawFd) -> bool {
+    unsafe { libc::fcntl(fd, libc::F_GETFD) != -1 }
+}
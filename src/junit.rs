@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::barkeeper::{OutputStream, StateTracker, ThreadStateTracker};
+use crate::error::*;
+use crate::queue::{JobState, Summary};
+use crate::JobRealization;
+
+
+type Results = Arc<Mutex<HashMap<JobRealization, JobResult>>>;
+
+
+/// Tracker writing a JUnit XML report at the end of the run
+///
+/// Without a path all calls are just passed on to the inner tracker.
+pub struct JunitTracker<T: StateTracker> {
+    inner: T,
+    path: Option<String>,
+    jobs: Mutex<Vec<JobRealization>>,
+    results: Results,
+}
+
+pub struct ThreadJunitTracker<T: ThreadStateTracker> {
+    inner: T,
+    results: Option<Results>,
+}
+
+struct JobResult {
+    state: JobState,
+    duration: Duration,
+    error: Option<String>,
+    exit_code: Option<i32>,
+
+    /// Full output of failed jobs
+    output: Vec<String>,
+}
+
+
+impl<T: StateTracker> JunitTracker<T> {
+    pub fn new(inner: T, path: Option<String>) -> Self {
+        JunitTracker {
+            inner,
+            path,
+            jobs: Mutex::new(Vec::new()),
+            results: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn write_report(&self, path: &str, summary: &Summary) -> ZinnResult<()> {
+        let jobs = self.jobs.lock().unwrap();
+        let results = self.results.lock().unwrap();
+        // jobs that did not run at all are reported as skipped as well
+        let nskipped = jobs.iter()
+            .filter(|j| results.get(*j).map(|r| r.state == JobState::Skipped).unwrap_or(true))
+            .count();
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(xml, "<testsuites>");
+        let _ = writeln!(xml, "  <testsuite name=\"zinn\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+            jobs.len(), summary.failed, nskipped, summary.wall_time.as_secs_f64());
+
+        for job in jobs.iter() {
+            let duration = results.get(job).map(|r| r.duration).unwrap_or_default();
+            let _ = write!(xml, "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                escape(&job.to_string()), escape(job.name()), duration.as_secs_f64());
+
+            match results.get(job) {
+                Some(JobResult { state: JobState::Skipped, .. }) => {
+                    let _ = writeln!(xml, ">\n      <skipped message=\"up to date\"/>\n    </testcase>");
+                },
+                Some(JobResult { state: JobState::Failed, error, exit_code, output, .. }) => {
+                    let message = error.as_deref().unwrap_or_default();
+                    let kind = match exit_code {
+                        Some(code) => format!("exit code {}", code),
+                        None => String::from("error"),
+                    };
+                    let _ = writeln!(xml, ">\n      <failure message=\"{}\" type=\"{}\">{}</failure>\n    </testcase>",
+                        escape(message), escape(&kind), escape(&output.join("\n")));
+                },
+                Some(_) => {
+                    let _ = writeln!(xml, "/>");
+                },
+                None => {
+                    let _ = writeln!(xml, ">\n      <skipped message=\"not run\"/>\n    </testcase>");
+                },
+            }
+        }
+
+        let _ = writeln!(xml, "  </testsuite>");
+        let _ = writeln!(xml, "</testsuites>");
+
+        fs::write(path, xml)?;
+        Ok(())
+    }
+}
+
+impl<T: StateTracker> StateTracker for JunitTracker<T> {
+    type ThreadStateTracker = ThreadJunitTracker<T::ThreadStateTracker>;
+
+    fn set_jobs(&self, jobs: &[JobRealization]) {
+        self.jobs.lock().unwrap().extend(jobs.iter().cloned());
+        self.inner.set_jobs(jobs);
+    }
+
+    fn start(&self) {
+        self.inner.start();
+    }
+
+    fn for_threads(&self, nthreads: usize) -> Vec<Self::ThreadStateTracker> {
+        let results = self.path.as_ref().map(|_| self.results.clone());
+        self.inner.for_threads(nthreads).into_iter()
+            .map(|inner| ThreadJunitTracker { inner, results: results.clone() })
+            .collect()
+    }
+
+    fn finish(&self, summary: &Summary) {
+        self.inner.finish(summary);
+        if let Some(path) = &self.path {
+            if let Err(e) = self.write_report(path, summary) {
+                eprintln!("Unable to write JUnit report - {}", e);
+            }
+        }
+    }
+}
+
+impl<T: ThreadStateTracker> ThreadStateTracker for ThreadJunitTracker<T> {
+    fn job_started(&mut self, job: &JobRealization) {
+        self.inner.job_started(job);
+    }
+
+    fn job_completed(&self, job: JobRealization, state: JobState, duration: Duration, error: Option<ZinnError>) {
+        if let Some(results) = &self.results {
+            let (exit_code, output) = match &error {
+                Some(ZinnError::ChildFailed { code, tail, log, .. }) => {
+                    // the log file has the full output, the tail lines are only a fallback
+                    let lines = match log.as_ref().and_then(|path| fs::read_to_string(path).ok()) {
+                        Some(contents) => contents.lines().map(str::to_owned).collect(),
+                        None => tail.iter().map(|l| console::strip_ansi_codes(l).to_string()).collect(),
+                    };
+                    (Some(*code), lines)
+                },
+                _ => (None, Vec::new()),
+            };
+            let error_msg = error.as_ref().map(|e| e.to_string());
+            let result = JobResult { state, duration, error: error_msg, exit_code, output };
+            results.lock().unwrap().insert(job.clone(), result);
+        }
+        self.inner.job_completed(job, state, duration, error);
+    }
+
+    fn start(&self) {
+        self.inner.start();
+    }
+
+    fn set_prefix(&mut self, prefix: String) {
+        self.inner.set_prefix(prefix);
+    }
+
+    fn clear_status(&mut self) {
+        self.inner.clear_status();
+    }
+
+    fn cmd_output(&mut self, out: &str, stream: OutputStream, verbose: bool) {
+        self.inner.cmd_output(out, stream, verbose);
+    }
+
+    fn flush_cmd_output(&mut self, job: &str, verbose: bool) {
+        self.inner.flush_cmd_output(job, verbose);
+    }
+
+    fn trace(&mut self, cmd: &str) {
+        self.inner.trace(cmd);
+    }
+
+    fn set_progress(&mut self, progress: f64) {
+        self.inner.set_progress(progress);
+    }
+
+    fn suspend<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        self.inner.suspend(f)
+    }
+}
+
+
+/// Escape special characters for XML attributes and text
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c if c.is_control() && c != '\n' && c != '\t' => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
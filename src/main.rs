@@ -1,37 +1,57 @@
 #![doc = include_str!("../README.md")]
 
-use barkeeper::{StateTracker, ThreadStateTracker};
-use clap::Parser;
+use barkeeper::{OutputSync, StateTracker, ThreadStateTracker};
+use clap::{Parser, Subcommand};
 use handlebars::Handlebars;
 use queue::Queue;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 use std::{env, fs, process, thread};
 
+use constants::Constant;
 use error::*;
+use events::EventTracker;
+use history::History;
 use job::*;
+use jobserver::Jobserver;
+use junit::JunitTracker;
+use workspace::Workspace;
 
 
+mod argument;
 mod barkeeper;
 mod constants;
 mod error;
+mod events;
 mod hbextensions;
+mod history;
 mod job;
+mod jobserver;
+mod junit;
 mod nix;
+mod pty;
 mod queue;
+mod shell;
+mod trace;
 mod worker;
+mod workspace;
 
 
 const DOCS_URL: &str = "https://jzbor.de/zinn/zinn";
 
 
 #[derive(Parser)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, disable_help_subcommand = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Zinnfile to run
-    #[clap(short, long, default_value_t = String::from("zinn.yaml"))]
+    #[clap(short, long, global = true, default_value_t = String::from("zinn.yaml"))]
     file: String,
 
     /// Target jobs to execute as entry points
@@ -39,13 +59,42 @@ struct Args {
     targets: Vec<String>,
 
     /// Number of jobs to run in parallel
+    ///
+    /// If not set and Zinn is called from a GNU make compatible jobserver (e.g. `make -j`),
+    /// jobs are limited by the tokens of that jobserver instead.
     #[clap(short, long)]
     jobs: Option<usize>,
 
+    /// Don't start new jobs while the load average is above this value
+    #[clap(short, long)]
+    load_average: Option<f64>,
+
+    /// Neither use an inherited jobserver nor provide one to child processes
+    #[clap(long)]
+    no_jobserver: bool,
+
     /// Print output of jobs
     #[clap(short, long)]
     verbose: bool,
 
+    /// Keep colors in the output of jobs if stdout is a terminal
+    #[clap(long)]
+    keep_colors: bool,
+
+    /// How to print output of jobs running in parallel
+    #[clap(long, value_enum, default_value_t = OutputSync::Line)]
+    output_sync: OutputSync,
+
+    /// Number of output lines to print when a job fails
+    #[clap(long, default_value_t = 20)]
+    tail: usize,
+
+    /// Directory to write the full output of each job to
+    ///
+    /// Defaults to `.zinn/<name of the Zinnfile>/logs`.
+    #[clap(long)]
+    log_dir: Option<String>,
+
     /// Force rebuild all files
     #[clap(short = 'B', long)]
     force_rebuild: bool,
@@ -67,7 +116,7 @@ struct Args {
     dry_run: bool,
 
     /// Set parameters for the initial job
-    #[clap(short, long, value_parser = parse_key_val::<String, String>)]
+    #[clap(short, long, global = true, value_parser = parse_key_val::<String, String>)]
     param: Vec<(String, String)>,
 
     /// Set or overwrite globals
@@ -79,6 +128,20 @@ struct Args {
     #[clap(short, long)]
     no_progress: bool,
 
+    /// Write a stream of JSON events to a file (`-` for stdout)
+    ///
+    /// With `-` all human-readable output of Zinn is printed to stderr instead.
+    #[clap(long)]
+    events: Option<String>,
+
+    /// Write a JUnit XML report with the results of all jobs
+    #[clap(long)]
+    junit: Option<String>,
+
+    /// Write a Chrome trace of the job timings (viewable with Perfetto or chrome://tracing)
+    #[clap(long)]
+    trace_file: Option<String>,
+
     /// Open an interactive shell containing the specified Nix packages
     #[clap(long)]
     nix_shell: bool,
@@ -92,35 +155,93 @@ struct Args {
     nix_run: Option<String>,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Show statistics on the durations of previous runs
+    Stats,
+
+    /// Show the description, arguments, dependencies and command of a job
+    ///
+    /// The command is rendered with the defaults and the parameters given with `--param`.
+    Help {
+        job: String,
+    },
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct Options {
     verbose: bool,
     force: bool,
     trace: bool,
     dry_run: bool,
+    tail: usize,
+    log_dir: String,
+    keep_colors: bool,
+    jobs: usize,
 }
 
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 struct Zinnfile {
+    /// Other Zinnfiles to merge constants and jobs from
+    ///
+    /// See also [`Include`].
+    #[serde(default)]
+    include: Vec<Include>,
+
     /// Constants to pass to the jobs
     ///
     /// All constants are rendered, with all previous constants being available.
     #[serde(default)]
     #[serde(deserialize_with = "constants::parse")]
-    constants: Vec<(String, String)>,
+    constants: Vec<(String, Constant)>,
+
+    /// Files with key/value pairs to load into the constants
+    ///
+    /// Paths are relative to the Zinnfile.
+    /// The values are also used for constants defaulting to environment variables.
+    #[serde(default)]
+    dotenv: Vec<String>,
 
     /// Descriptions of the jobs
     ///
     /// See also [`JobDescription`].
+    #[serde(default)]
     jobs: HashMap<String, JobDescription>,
 
+    /// Environment variables for all jobs
+    ///
+    /// The values are rendered for each job like its `run` field.
+    #[serde(default)]
+    env: HashMap<String, String>,
+
+    /// Interpreter for the `run` field of all jobs (`sh` by default)
+    ///
+    /// See also [`Shell`](shell::Shell).
+    shell: Option<shell::Shell>,
+
     /// Nix configuration
     ///
     /// See also [`NixConfig`]
     nix: Option<NixConfig>,
 }
 
+/// Zinnfile to include, either as plain path or with a namespace for its jobs
+///
+/// Paths are relative to the including file.
+/// Nix configurations of included files are ignored.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Include {
+    File(String),
+    Namespaced {
+        file: String,
+
+        /// Prefix for the names of the included jobs (e.g. `pkg` for `pkg.build`)
+        namespace: Option<String>,
+    },
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct NixConfig {
     /// Flake reference to a flake providing the required packages
@@ -131,13 +252,112 @@ struct NixConfig {
     packages: Vec<String>,
 }
 
+impl Zinnfile {
+    /// Read a Zinnfile and merge all files it includes
+    fn load(path: &Path) -> ZinnResult<Self> {
+        Self::load_included(path, "", &mut Vec::new(), &mut HashSet::new())
+    }
+
+    /// Load a Zinnfile whose jobs end up with the namespace `prefix` in the top-level file
+    ///
+    /// Files that were already merged with the same prefix (e.g. by a diamond include)
+    /// yield an empty Zinnfile.
+    fn load_included(path: &Path, prefix: &str, stack: &mut Vec<PathBuf>, merged: &mut HashSet<(PathBuf, String)>) -> ZinnResult<Self> {
+        let canonic_path = path.canonicalize()?;
+        if stack.contains(&canonic_path) {
+            return Err(ZinnError::IncludeCycle(path.display().to_string()));
+        }
+        if !merged.insert((canonic_path.clone(), prefix.to_owned())) {
+            return Ok(Zinnfile::default());
+        }
+
+        let contents = fs::read_to_string(path)?;
+        let mut zinnfile: Zinnfile = serde_yaml::from_str(&contents)?;
+        // relative paths are resolved independently of the current directory
+        let base = canonic_path.parent().ok_or(ZinnError::ChdirError())?.to_owned();
+
+        // included constants and variables come first, so they can be overridden by the including file
+        let mut constants = Vec::new();
+        let mut jobs = HashMap::new();
+        // file each job comes from, to report conflicts
+        let mut origins = HashMap::new();
+        let mut env = HashMap::new();
+        let mut dotenv = Vec::new();
+        let mut shell = None;
+        stack.push(canonic_path);
+        for include in &zinnfile.include {
+            let (file, namespace) = match include {
+                Include::File(file) => (file, None),
+                Include::Namespaced { file, namespace } => (file, namespace.as_ref()),
+            };
+            let included_prefix = match namespace {
+                Some(namespace) => format!("{}{}.", prefix, namespace),
+                None => prefix.to_owned(),
+            };
+            let included = Self::load_included(&base.join(file), &included_prefix, stack, merged)
+                .map_err(|e| ZinnError::Include(file.to_owned(), Box::new(e)))?;
+            constants.extend(included.constants);
+            env.extend(included.env);
+            dotenv.extend(included.dotenv);
+            shell = shell.or(included.shell);
+
+            let local_jobs = included.jobs.keys().cloned().collect();
+            for (name, mut job) in included.jobs {
+                let name = match namespace {
+                    Some(namespace) => {
+                        job.add_namespace(namespace, &local_jobs);
+                        format!("{}.{}", namespace, name)
+                    },
+                    None => name,
+                };
+                if let Some(other) = origins.insert(name.clone(), file.to_owned()) {
+                    return Err(ZinnError::ConflictingJob(name, other, file.to_owned()));
+                }
+                jobs.insert(name, job);
+            }
+        }
+        stack.pop();
+
+        constants.append(&mut zinnfile.constants);
+        for (name, job) in zinnfile.jobs.drain() {
+            if let Some(other) = origins.get(&name) {
+                return Err(ZinnError::ConflictingJob(name, other.to_owned(), path.display().to_string()));
+            }
+            jobs.insert(name, job);
+        }
+        env.extend(zinnfile.env.drain());
+        dotenv.extend(zinnfile.dotenv.iter().map(|file| base.join(file).display().to_string()));
+        zinnfile.constants = constants;
+        zinnfile.env = env;
+        zinnfile.dotenv = dotenv;
+        zinnfile.shell = zinnfile.shell.or(shell);
+        zinnfile.jobs = jobs;
+
+        Ok(zinnfile)
+    }
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Stats => "stats",
+            Command::Help { .. } => "help",
+        }
+    }
+}
+
 impl Args {
-    fn options(&self) -> Options {
+    fn options(&self, nthreads: usize) -> Options {
         Options {
             verbose: self.verbose,
             force: self.force_rebuild,
             trace: self.trace,
             dry_run: self.dry_run,
+            tail: self.tail,
+            log_dir: self.log_dir.clone()
+                .unwrap_or_else(|| history::state_dir(Path::new(&self.file)).join("logs").display().to_string()),
+            keep_colors: self.keep_colors && console::Term::stdout().is_term(),
+            jobs: nthreads,
         }
     }
 }
@@ -161,6 +381,71 @@ fn render_component(path: &[&str], template: &str, handlebars: &mut Handlebars,
 }
 
 
+fn print_stats(history: &History) {
+    println!("{:>5} {:>10} {:>10} {:>8}  JOB", "RUNS", "AVG", "MAX", "TREND");
+    for stats in history.stats() {
+        let trend = match stats.trend {
+            Some(trend) => format!("{:+.0}%", trend * 100.0),
+            None => String::from("-"),
+        };
+        println!("{:>5} {:>9.2}s {:>9.2}s {:>8}  {}",
+            stats.runs, stats.avg.as_secs_f64(), stats.max.as_secs_f64(), trend, stats.job);
+    }
+}
+
+fn print_job_help(workspace: &mut Workspace, scope: &Arc<workspace::Scope>, name: &str, parameters: &HashMap<String, String>) -> ZinnResult<()> {
+    let (job_scope, job_name) = workspace.lookup(scope, name)?;
+    let job = job_scope.jobs().get(&job_name)
+        .ok_or_else(|| ZinnError::JobNotFound(name.to_owned()))?;
+
+    println!("{}", console::style(name).bold());
+    if let Some(description) = job.description() {
+        println!("{}", description.trim_end());
+    }
+
+    if !job.args().is_empty() {
+        println!("\nArguments:");
+        for arg in job.args() {
+            print!("  {}", console::style(arg.name()).cyan());
+            if let Some(expected) = arg.expected() {
+                print!(" <{}>", expected);
+            }
+            if let Some(default) = job.default(arg.name()) {
+                print!(" (default: {})", default);
+            }
+            if let Some(help) = arg.help() {
+                print!(" - {}", help);
+            }
+            println!();
+        }
+    }
+
+    // the command can only be shown if all arguments have a value
+    let realization = job.realize(&job_name, &job_scope, workspace, parameters);
+    let dependencies = match &realization {
+        Ok(realization) => realization.dependencies().iter().map(|d| d.to_string()).collect(),
+        Err(_) => job.dependency_names().iter().map(|d| d.to_string()).collect::<Vec<_>>(),
+    };
+    if !dependencies.is_empty() {
+        println!("\nDependencies:");
+        for dep in dependencies {
+            println!("  {}", dep);
+        }
+    }
+
+    println!("\nCommand:");
+    match realization {
+        Ok(realization) => {
+            for line in realization.steps().iter().flat_map(|s| s.lines()) {
+                println!("  {}", line);
+            }
+        },
+        Err(e) => println!("  {}", console::style(format!("unable to render - {}", e)).red()),
+    }
+
+    Ok(())
+}
+
 /// Parse a single key-value pair
 fn parse_key_val<T, U>(s: &str) -> Result<(T, U), Box<dyn Error + Send + Sync + 'static>>
 where
@@ -176,14 +461,16 @@ where
 }
 
 
-fn run<T: StateTracker>(barkeeper: T, nthreads: usize, queue: Queue, args: Args)
+fn run<T: StateTracker>(barkeeper: T, nthreads: usize, queue: Queue, jobserver: Option<Arc<Jobserver>>, args: Args)
 where
     <T as StateTracker>::ThreadStateTracker: 'static
 {
 
+    let start = Instant::now();
+
     // setup bars
     let mut thread_barkeepers = barkeeper.for_threads(nthreads);
-    barkeeper.set_njobs(queue.len());
+    barkeeper.set_jobs(&queue.jobs());
 
     // start worker bars
     for tb in &thread_barkeepers {
@@ -191,13 +478,14 @@ where
     }
 
     // start the threads
-    let threads: Vec<_> = (0..nthreads).map(|_| {
+    let threads: Vec<_> = (0..nthreads).map(|worker| {
         let queue = queue.clone();
         let tb: T::ThreadStateTracker = thread_barkeepers.pop().unwrap();
-        let options = args.options();
+        let options = args.options(nthreads);
+        let jobserver = jobserver.clone();
 
         thread::spawn(move || {
-            worker::run_worker(queue, tb, options)
+            worker::run_worker(worker, queue, tb, options, jobserver)
         })
     }).collect();
 
@@ -210,11 +498,36 @@ where
         let _ = thread.join();
     }
 
+    barkeeper.finish(&queue.summary(start.elapsed()));
+
+    if let Some(path) = &args.trace_file {
+        if let Err(e) = trace::write(path, &queue.records(), queue.created(), nthreads) {
+            eprintln!("Unable to write trace - {}", e);
+        }
+    }
+
+    // remember durations for scheduling future runs
+    if !args.dry_run {
+        if let Err(e) = queue.save_history() {
+            eprintln!("Unable to save history - {}", e);
+        }
+    }
+
     if queue.has_failed() {
         process::exit(1);
     }
 }
 
+/// Wrap the tracker with the requested reports
+fn run_with_reports<T: StateTracker + 'static>(barkeeper: T, nthreads: usize, queue: Queue, jobserver: Option<Arc<Jobserver>>, args: Args)
+where
+    <T as StateTracker>::ThreadStateTracker: 'static
+{
+    let tracker = resolve(EventTracker::new(barkeeper, args.events.as_deref()));
+    let tracker = JunitTracker::new(tracker, args.junit.clone());
+    run(tracker, nthreads, queue, jobserver, args)
+}
+
 fn main() {
     let args = Args::parse();
 
@@ -229,25 +542,39 @@ fn main() {
         }
         return;
     }
-    let nthreads = if let Some(nthreads) = args.jobs {
-        nthreads
-    } else if let Ok(nthreads) = thread::available_parallelism() {
-        nthreads.into()
-    } else { 4 };
 
     // read zinnfile
-    let contents = resolve(fs::read_to_string(&args.file));
-    let mut zinnfile: Zinnfile = resolve(serde_yaml::from_str(&contents));
-    zinnfile.constants.extend(args.override_const.iter().cloned());
+    let zinnfile = resolve(Zinnfile::load(Path::new(&args.file)));
+    // subcommands take precedence over jobs of the same name
+    if let Some(command) = &args.command {
+        if zinnfile.jobs.contains_key(command.name()) {
+            eprintln!("Job \"{0}\" is shadowed by the subcommand, run it with `zinn -- {0}`", command.name());
+        }
+    }
 
     // --list
     if args.list {
-        for (name, job) in zinnfile.jobs {
+        let jobs: BTreeMap<_, _> = zinnfile.jobs.iter().collect();
+        for (name, job) in jobs {
             print!("{}", name);
             if !job.args().is_empty() {
-                print!(" ({})", job.args().join(", "));
+                let args: Vec<_> = job.args().iter()
+                    .map(|arg| match job.default(arg.name()) {
+                        Some(default) => format!("{}={}", arg.name(), default),
+                        None => arg.name().to_owned(),
+                    })
+                    .collect();
+                print!(" ({})", args.join(", "));
+            }
+            if let Some(description) = job.description().and_then(|d| d.lines().next()) {
+                print!(" - {}", description);
             }
             println!();
+            for arg in job.args() {
+                if let Some(help) = arg.help() {
+                    println!("    {}: {}", arg.name(), help);
+                }
+            }
         }
         return;
     }
@@ -282,6 +609,12 @@ fn main() {
         .ok_or(ZinnError::ChdirError());
     resolve(env::set_current_dir(resolve(parent)));
 
+    // stats
+    if let Some(Command::Stats) = args.command {
+        print_stats(&History::load(history::state_dir(Path::new(&args.file))));
+        return;
+    }
+
     // init template engine
     let mut handlebars = Handlebars::new();
     handlebars.set_strict_mode(true);
@@ -289,19 +622,46 @@ fn main() {
     hbextensions::register_helpers(&mut handlebars);
 
     // parse constants
-    let mut constants = HashMap::new();
-    for (name, value) in &zinnfile.constants {
-        let template_path = ["constants", name];
-        let realized = resolve(render_component(&template_path, value, &mut handlebars, &constants));
-        constants.insert(name.to_owned(), realized);
+    let (mut workspace, scope) = resolve(Workspace::new(&canonic_zinn_path, zinnfile, args.override_const.clone(), handlebars));
+
+    // help
+    if let Some(Command::Help { job }) = &args.command {
+        let parameters = args.param.iter().cloned().collect();
+        resolve(print_job_help(&mut workspace, &scope, job, &parameters));
+        return;
     }
 
+    // share job tokens with recursive make/cargo invocations
+    let inherited_jobserver = if args.no_jobserver || args.jobs.is_some() {
+        None
+    } else {
+        Jobserver::from_env()
+    };
+    let nthreads = if let Some(nthreads) = args.jobs {
+        nthreads
+    } else if let Some(nthreads) = inherited_jobserver.as_ref().and_then(|js| js.njobs()) {
+        nthreads
+    } else if let Ok(nthreads) = thread::available_parallelism() {
+        nthreads.into()
+    } else { 4 };
+    let jobserver = if args.no_jobserver {
+        None
+    } else if let Some(jobserver) = inherited_jobserver {
+        Some(Arc::new(jobserver))
+    } else {
+        let jobserver = resolve(Jobserver::new(nthreads));
+        env::set_var("MAKEFLAGS", jobserver.makeflags(nthreads));
+        Some(Arc::new(jobserver))
+    };
+
     // feed the queue
-    let queue = Queue::new();
+    let history = History::load(history::state_dir(Path::new(&args.file)));
+    let queue = Queue::new(history.clone(), args.load_average);
     let parameters = args.param.iter().cloned().collect();
     for name in &args.targets {
-        let job = match zinnfile.jobs.get(name) {
-            Some(job) => resolve(job.realize(name, &zinnfile.jobs, &mut handlebars, &constants, &parameters)),
+        let (job_scope, job_name) = resolve(workspace.lookup(&scope, name));
+        let job = match job_scope.jobs().get(&job_name) {
+            Some(job) => resolve(job.realize(&job_name, &job_scope, &mut workspace, &parameters)),
             None => resolve(Err(ZinnError::JobNotFound(name.to_owned()))),
         };
         for dep in job.transitive_dependencies() {
@@ -310,13 +670,16 @@ fn main() {
         queue.enqueue(job);
     }
 
+    // keep the event stream on stdout free of anything else
+    let events_on_stdout = args.events.as_deref() == Some("-");
+
     #[cfg(feature = "progress")]
-    if args.no_progress || queue.has_interactive() {
-        run(barkeeper::DummyBarkeeper::new(), nthreads, queue, args);
+    if args.no_progress {
+        run_with_reports(barkeeper::DummyBarkeeper::new(args.output_sync, events_on_stdout), nthreads, queue, jobserver, args);
     } else {
-        run(barkeeper::Barkeeper::new(), nthreads, queue, args);
+        run_with_reports(barkeeper::Barkeeper::new(args.output_sync, history), nthreads, queue, jobserver, args);
     }
 
     #[cfg(not(feature = "progress"))]
-    run(barkeeper::DummyBarkeeper::new(), nthreads, queue, args);
+    run_with_reports(barkeeper::DummyBarkeeper::new(args.output_sync, events_on_stdout), nthreads, queue, jobserver, args);
 }
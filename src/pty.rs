@@ -0,0 +1,64 @@
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::ptr;
+
+use crate::error::*;
+
+
+/// Terminal size used if Zinn itself does not run in a terminal
+const DEFAULT_SIZE: (u16, u16) = (24, 80);
+
+
+/// Pseudo-terminal pair
+pub struct Pty {
+    /// Side read by Zinn
+    pub master: File,
+
+    /// Side passed to the child process
+    pub slave: File,
+}
+
+impl Pty {
+    /// Allocate a new pseudo-terminal with the size of the current terminal
+    pub fn open() -> ZinnResult<Self> {
+        let (rows, cols) = console::Term::stdout().size_checked().unwrap_or(DEFAULT_SIZE);
+        let mut size = libc::winsize { ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0 };
+
+        // openpty() is available on Linux, the BSDs and macOS alike
+        // (macOS expects mutable pointers, Linux const ones)
+        let mut master_fd = -1;
+        let mut slave_fd = -1;
+        let result = unsafe {
+            libc::openpty(&mut master_fd, &mut slave_fd, ptr::null_mut(), ptr::null_mut(), ptr::addr_of_mut!(size))
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let master = unsafe { File::from_raw_fd(master_fd) };
+        let slave = unsafe { File::from_raw_fd(slave_fd) };
+
+        // only the standard streams of the child should refer to the terminal
+        for fd in [master.as_raw_fd(), slave.as_raw_fd()] {
+            if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+
+        Ok(Pty { master, slave })
+    }
+}
+
+/// Make the terminal on stderr the controlling terminal of the child process
+pub fn set_controlling_terminal(command: &mut Command) {
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() < 0 || libc::ioctl(libc::STDERR_FILENO, libc::TIOCSCTTY as _, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
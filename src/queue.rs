@@ -1,14 +1,27 @@
-use std::{collections::{HashMap, VecDeque}, sync::{Arc, Condvar, Mutex}};
+use std::{collections::{HashMap, VecDeque}, fs, sync::{Arc, Condvar, Mutex}, time::{Duration, Instant}};
 
+use serde::Serialize;
+
+use crate::history::{History, DEFAULT_ESTIMATE};
+use crate::error::*;
 use crate::JobRealization;
 
+
+/// Interval in which the load average is checked again while it is too high
+const LOAD_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Number of jobs listed as slowest in the summary
+const SUMMARY_SLOWEST: usize = 5;
+
+
 #[derive(Clone)]
 pub struct Queue {
     inner: Arc<Mutex<InnerQueue>>,
     cond_fetch_job: Arc<Condvar>,
 }
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum JobState {
     Ready,
     Failed,
@@ -17,20 +30,78 @@ pub enum JobState {
     Skipped,
 }
 
+/// When and on which worker a job was run
+#[derive(Clone, Copy, Debug)]
+pub struct JobTiming {
+    pub worker: usize,
+    pub start: Instant,
+    pub end: Instant,
+}
+
+/// Execution details of a completed job
+#[derive(Clone, Debug)]
+pub struct JobRecord {
+    pub job: JobRealization,
+    pub state: JobState,
+    pub timing: JobTiming,
+
+    /// Time at which all dependencies of the job were completed
+    pub ready: Instant,
+}
+
+/// Results of a run
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    pub finished: usize,
+    pub skipped: usize,
+    pub failed: usize,
+
+    /// Jobs that were not run, because one of their dependencies failed
+    pub blocked: usize,
+
+    /// Jobs that were not run, because the build was aborted
+    pub cancelled: usize,
+
+    pub wall_time: Duration,
+
+    /// Jobs that took the longest, in descending order
+    pub slowest: Vec<(JobRealization, Duration)>,
+
+    pub failed_jobs: Vec<JobRealization>,
+}
+
 struct InnerQueue {
     jobs: VecDeque<JobRealization>,
     states: HashMap<JobRealization, JobState>,
     done: bool,
     failed: bool,
+    history: History,
+
+    /// Time at which the queue was created
+    created: Instant,
+
+    /// Execution times of each completed job
+    timings: HashMap<JobRealization, JobTiming>,
+
+    /// Don't start new jobs while the system load is above this value
+    max_load: Option<f64>,
+
+    /// Estimated duration of the longest path from a job to the end of the build
+    priorities: HashMap<JobRealization, Duration>,
 }
 
 impl Queue {
-    pub fn new() -> Self {
+    pub fn new(history: History, max_load: Option<f64>) -> Self {
         let inner = InnerQueue {
             jobs: VecDeque::new(),
             states: HashMap::new(),
             done: false,
             failed: false,
+            history,
+            created: Instant::now(),
+            timings: HashMap::new(),
+            max_load,
+            priorities: HashMap::new(),
         };
         Queue {
             inner: Arc::new(Mutex::new(inner)),
@@ -43,6 +114,7 @@ impl Queue {
         if inner.jobs.contains(&job) { return; }
         inner.jobs.push_back(job.clone());
         inner.states.insert(job, JobState::Ready);
+        inner.priorities.clear();
         drop(inner);
 
         self.cond_fetch_job.notify_one();
@@ -55,6 +127,11 @@ impl Queue {
                 return None;
             }
 
+            if inner.load_exceeded() {
+                inner = self.cond_fetch_job.wait_timeout(inner, LOAD_POLL_INTERVAL).unwrap().0;
+                continue;
+            }
+
             match inner.get_ready() {
                 Some(job) => return Some(job),
                 None => { inner = self.cond_fetch_job.wait(inner).unwrap(); },
@@ -62,8 +139,12 @@ impl Queue {
         }
     }
 
-    pub fn finished(&self, job: JobRealization, state: JobState) {
+    pub fn finished(&self, job: JobRealization, state: JobState, timing: JobTiming) {
         let mut inner = self.inner.lock().unwrap();
+        if state == JobState::Finished {
+            inner.history.record(&job.to_string(), timing.duration());
+        }
+        inner.timings.insert(job.clone(), timing);
         inner.states.insert(job, state);
         if state == JobState::Failed {
             inner.failed = true;
@@ -76,17 +157,76 @@ impl Queue {
         self.cond_fetch_job.notify_all();
     }
 
-    pub fn len(&self) -> usize {
-        self.inner.lock().unwrap().jobs.len()
+    pub fn jobs(&self) -> Vec<JobRealization> {
+        self.inner.lock().unwrap().jobs.iter().cloned().collect()
     }
 
     pub fn has_failed(&self) -> bool {
         self.inner.lock().unwrap().failed
     }
 
-    #[cfg(feature = "progress")]
-    pub fn has_interactive(&self) -> bool {
-        self.inner.lock().unwrap().jobs.iter().any(|j| j.is_interactive())
+    pub fn summary(&self, wall_time: Duration) -> Summary {
+        let inner = self.inner.lock().unwrap();
+        let mut summary = Summary { wall_time, ..Default::default() };
+
+        for (job, state) in &inner.states {
+            match state {
+                JobState::Finished => summary.finished += 1,
+                JobState::Skipped => summary.skipped += 1,
+                JobState::Failed => {
+                    summary.failed += 1;
+                    summary.failed_jobs.push(job.clone());
+                },
+                JobState::Ready | JobState::Running => {
+                    let blocked = job.transitive_dependencies().iter()
+                        .any(|d| inner.states.get(d) == Some(&JobState::Failed));
+                    if blocked {
+                        summary.blocked += 1;
+                    } else {
+                        summary.cancelled += 1;
+                    }
+                },
+            }
+        }
+
+        let mut slowest: Vec<_> = inner.timings.iter()
+            .filter(|(job, _)| inner.states.get(*job) == Some(&JobState::Finished))
+            .map(|(job, timing)| (job.clone(), timing.duration()))
+            .collect();
+        slowest.sort_by(|(_, d1), (_, d2)| d2.cmp(d1));
+        slowest.truncate(SUMMARY_SLOWEST);
+        summary.slowest = slowest;
+
+        summary
+    }
+
+    /// Execution details of all completed jobs in the order they were started
+    pub fn records(&self) -> Vec<JobRecord> {
+        let inner = self.inner.lock().unwrap();
+        let mut records: Vec<_> = inner.timings.iter()
+            .map(|(job, timing)| {
+                let ready = job.dependencies().iter()
+                    .filter_map(|d| inner.timings.get(d))
+                    .map(|t| t.end)
+                    .fold(inner.created, Instant::max);
+                JobRecord { job: job.clone(), state: inner.states[job], timing: *timing, ready }
+            }).collect();
+        records.sort_by_key(|r| r.timing.start);
+        records
+    }
+
+    pub fn created(&self) -> Instant {
+        self.inner.lock().unwrap().created
+    }
+
+    pub fn save_history(&self) -> ZinnResult<()> {
+        self.inner.lock().unwrap().history.save()
+    }
+}
+
+impl JobTiming {
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
     }
 }
 
@@ -107,6 +247,19 @@ impl InnerQueue {
         }
     }
 
+    /// Whether the system is too busy to start another job
+    ///
+    /// A job is always allowed to start if nothing else is running.
+    fn load_exceeded(&self) -> bool {
+        let max_load = match self.max_load {
+            Some(max_load) => max_load,
+            None => return false,
+        };
+
+        let running = self.states.values().any(|s| *s == JobState::Running);
+        running && load_average().is_some_and(|load| load > max_load)
+    }
+
     fn dependencies_satisfied(&self, job: JobRealization) -> bool {
         for dep in job.dependencies() {
             if !self.is_completed_successfully(dep) {
@@ -117,13 +270,24 @@ impl InnerQueue {
         true
     }
 
+    /// Pick the ready job with the longest estimated path to the end of the build
     fn get_ready(&mut self) -> Option<JobRealization> {
-        let mut ret = None;
+        if self.priorities.len() != self.states.len() {
+            self.update_priorities();
+        }
+
+        let mut ret: Option<JobRealization> = None;
         for job in &self.jobs {
             if *self.states.get(job).unwrap() == JobState::Ready
                 && self.dependencies_satisfied(job.clone()) {
 
-                ret = Some(job.clone())
+                let better = match &ret {
+                    Some(current) => self.priorities.get(job) > self.priorities.get(current),
+                    None => true,
+                };
+                if better {
+                    ret = Some(job.clone())
+                }
             }
         }
 
@@ -135,6 +299,20 @@ impl InnerQueue {
         ret
     }
 
+    fn update_priorities(&mut self) {
+        let mut dependents: HashMap<JobRealization, Vec<JobRealization>> = HashMap::new();
+        for job in self.states.keys() {
+            for dep in job.dependencies() {
+                dependents.entry(dep).or_default().push(job.clone());
+            }
+        }
+
+        self.priorities.clear();
+        for job in self.states.keys() {
+            critical_path(job, &dependents, &self.history, &mut self.priorities);
+        }
+    }
+
     /// Determines whether the task is running or may be run in the future
     fn task_alive(&self, job: JobRealization) -> bool {
         if self.is_completed(job.clone()) {
@@ -161,3 +339,29 @@ impl InnerQueue {
         false
     }
 }
+
+/// Read the one minute load average of the system
+fn load_average() -> Option<f64> {
+    fs::read_to_string("/proc/loadavg").ok()?
+        .split_whitespace()
+        .next()?
+        .parse().ok()
+}
+
+/// Estimated duration of a job plus its longest chain of dependents
+fn critical_path(job: &JobRealization, dependents: &HashMap<JobRealization, Vec<JobRealization>>,
+                 history: &History, priorities: &mut HashMap<JobRealization, Duration>) -> Duration {
+    if let Some(priority) = priorities.get(job) {
+        return *priority;
+    }
+
+    let mut downstream = Duration::ZERO;
+    for dependent in dependents.get(job).into_iter().flatten() {
+        downstream = downstream.max(critical_path(dependent, dependents, history, priorities));
+    }
+
+    let estimate = history.estimate(&job.to_string()).unwrap_or(DEFAULT_ESTIMATE);
+    let priority = estimate + downstream;
+    priorities.insert(job.clone(), priority);
+    priority
+}
@@ -0,0 +1,110 @@
+use std::path::Path;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::*;
+
+
+/// Shell used if neither the job nor the Zinnfile specify one
+const DEFAULT_SHELL: &str = "sh";
+
+/// Shells that understand `set -e`
+const POSIX_SHELLS: &[&str] = &["sh", "ash", "bash", "dash", "ksh", "mksh", "zsh"];
+
+
+/// Interpreter for the `run` field of jobs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Shell {
+    /// Name of an interpreter, e.g. `bash`, `python3` or `node`
+    Name(String),
+
+    /// Command line the script is appended to, e.g. `[bash, -o, pipefail, -c]`
+    Argv(Vec<String>),
+}
+
+
+impl Shell {
+    /// Command line the script is appended to
+    pub fn argv(&self) -> ZinnResult<Vec<String>> {
+        match self {
+            Shell::Name(name) => {
+                let flag = match name.as_str() {
+                    "node" | "perl" | "ruby" => "-e",
+                    _ => "-c",
+                };
+                Ok(vec![name.to_owned(), flag.to_owned()])
+            },
+            Shell::Argv(argv) if argv.is_empty() => Err(ZinnError::EmptyShell()),
+            Shell::Argv(argv) => Ok(argv.clone()),
+        }
+    }
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Shell::Name(DEFAULT_SHELL.to_owned())
+    }
+}
+
+/// Create a command running `script` with the interpreter given by `argv`
+///
+/// POSIX shells are told to exit on the first failing command.
+pub fn command(argv: &[String], script: &str) -> Command {
+    let script = if is_posix(argv) {
+        format!("set -e; {}", script)
+    } else {
+        script.to_owned()
+    };
+
+    let mut command = Command::new(&argv[0]);
+    command.args(&argv[1..]).arg(script);
+    command
+}
+
+/// Check whether the interpreter given by `argv` is a POSIX shell
+pub fn is_posix(argv: &[String]) -> bool {
+    Path::new(&argv[0]).file_name()
+        .map(|name| POSIX_SHELLS.contains(&name.to_string_lossy().as_ref()))
+        .unwrap_or(false)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(shell: &Shell) -> Vec<String> {
+        shell.argv().unwrap()
+    }
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn argv_of_names() {
+        assert_eq!(argv(&Shell::default()), strings(&["sh", "-c"]));
+        assert_eq!(argv(&Shell::Name(String::from("python3"))), strings(&["python3", "-c"]));
+        assert_eq!(argv(&Shell::Name(String::from("node"))), strings(&["node", "-e"]));
+        assert_eq!(argv(&Shell::Name(String::from("perl"))), strings(&["perl", "-e"]));
+    }
+
+    #[test]
+    fn argv_of_command_lines() {
+        let shell = Shell::Argv(strings(&["bash", "-o", "pipefail", "-c"]));
+        assert_eq!(argv(&shell), strings(&["bash", "-o", "pipefail", "-c"]));
+        assert!(matches!(Shell::Argv(Vec::new()).argv(), Err(ZinnError::EmptyShell())));
+    }
+
+    #[test]
+    fn posix_shells() {
+        assert!(is_posix(&strings(&["sh", "-c"])));
+        assert!(is_posix(&strings(&["/usr/bin/bash", "-o", "pipefail", "-c"])));
+        assert!(is_posix(&strings(&["zsh"])));
+        assert!(!is_posix(&strings(&["python3", "-c"])));
+        assert!(!is_posix(&strings(&["fish", "-c"])));
+        assert!(!is_posix(&strings(&["/bin/shell", "-c"])));
+    }
+}
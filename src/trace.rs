@@ -0,0 +1,67 @@
+use std::fs;
+use std::time::Instant;
+
+use serde_json::json;
+
+use crate::error::*;
+use crate::queue::JobRecord;
+
+
+/// Process id used for all events
+const PID: usize = 1;
+
+
+/// Write a Chrome trace (viewable with Perfetto or `chrome://tracing`)
+///
+/// Each job is a span on the worker thread that ran it.
+pub fn write(path: &str, records: &[JobRecord], origin: Instant, nthreads: usize) -> ZinnResult<()> {
+    let micros = |instant: Instant| instant.saturating_duration_since(origin).as_micros() as u64;
+
+    let mut events: Vec<_> = (0..nthreads).map(|worker| json!({
+        "name": "thread_name",
+        "ph": "M",
+        "pid": PID,
+        "tid": worker,
+        "args": { "name": format!("worker {}", worker) },
+    })).collect();
+
+    for (id, record) in records.iter().enumerate() {
+        let queue_wait = record.timing.start.saturating_duration_since(record.ready);
+
+        // waiting is shown as async span, as multiple jobs may wait at the same time
+        if !queue_wait.is_zero() {
+            let wait = json!({
+                "name": record.job.to_string(),
+                "cat": "queue",
+                "id": id,
+                "pid": PID,
+                "tid": nthreads,
+            });
+            let mut begin = wait.clone();
+            begin["ph"] = json!("b");
+            begin["ts"] = json!(micros(record.ready));
+            let mut end = wait;
+            end["ph"] = json!("e");
+            end["ts"] = json!(micros(record.timing.start));
+            events.extend([begin, end]);
+        }
+
+        events.push(json!({
+            "name": record.job.to_string(),
+            "cat": "job",
+            "ph": "X",
+            "pid": PID,
+            "tid": record.timing.worker,
+            "ts": micros(record.timing.start),
+            "dur": record.timing.duration().as_micros() as u64,
+            "args": {
+                "state": record.state,
+                "queue_wait_ms": queue_wait.as_secs_f64() * 1000.0,
+            },
+        }));
+    }
+
+    let trace = json!({ "traceEvents": events, "displayTimeUnit": "ms" });
+    fs::write(path, serde_json::to_string(&trace)?)?;
+    Ok(())
+}
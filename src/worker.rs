@@ -1,33 +1,49 @@
+use std::sync::Arc;
+use std::time::Instant;
+
 use crate::barkeeper::ThreadStateTracker;
-use crate::queue::{JobState, Queue};
+use crate::job::InnerJobRealization;
+use crate::jobserver::Jobserver;
+use crate::queue::{JobState, JobTiming, Queue};
 use crate::Options;
 
 
 const MAX_PREFIX_LEN: usize = 60;
 
 
-pub fn run_worker(queue: Queue, mut tracker: impl ThreadStateTracker, options: Options) {
+pub fn run_worker(worker: usize, queue: Queue, mut tracker: impl ThreadStateTracker, options: Options, jobserver: Option<Arc<Jobserver>>) {
     loop {
         tracker.set_prefix(String::from("waiting..."));
         tracker.clear_status();
 
         if let Some(job) = queue.fetch() {
-            let prefix = console::style(truncate_ellipse(job.to_string(), MAX_PREFIX_LEN)).cyan().to_string();
-            tracker.set_prefix(prefix);
+            tracker.set_prefix(job_prefix(&job));
             // tracker.set_prefix(job.to_string());
-            let result = job.run(&mut tracker, &options);
+            tracker.job_started(&job);
+            // wait for a token before starting the job
+            let token = jobserver.as_ref().map(|js| js.acquire()).transpose();
+            let start = Instant::now();
+            let result = token.and_then(|_token| job.run(&mut tracker, &options));
+            let timing = JobTiming { worker, start, end: Instant::now() };
+            // the job may have shown its current step in the prefix
+            tracker.set_prefix(job_prefix(&job));
             let state = match &result {
                 Ok(state) => *state,
                 Err(_) => JobState::Failed,
             };
-            tracker.job_completed(job.clone(), state, result.err());
-            queue.finished(job, state);
+            tracker.job_completed(job.clone(), state, timing.duration(), result.err());
+            queue.finished(job, state, timing);
         } else {
             break;
         }
     }
 }
 
+/// Prefix for the status and output of a job
+pub fn job_prefix(job: &InnerJobRealization) -> String {
+    console::style(truncate_ellipse(job.to_string(), MAX_PREFIX_LEN)).cyan().to_string()
+}
+
 fn truncate_ellipse(mut string: String, max_size: usize) -> String {
     if string.len() > max_size {
         // UTF-8 is weird...
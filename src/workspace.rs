@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use handlebars::Handlebars;
+
+use crate::constants::{self, Constant};
+use crate::error::*;
+use crate::job::JobDescription;
+use crate::shell::Shell;
+use crate::{render_component, Zinnfile};
+
+
+/// Separator between the path of a Zinnfile and a job name (e.g. `libfoo/zinn.yaml:build`)
+const FILE_SEPARATOR: char = ':';
+
+
+/// All Zinnfiles jobs are realized from
+///
+/// Zinnfiles referenced by jobs of other files are loaded lazily and only once.
+pub struct Workspace<'a> {
+    /// Directory of the top-level Zinnfile
+    root: PathBuf,
+    handlebars: Handlebars<'a>,
+    overrides: Vec<(String, String)>,
+    scopes: HashMap<PathBuf, Arc<Scope>>,
+}
+
+/// Jobs and constants of a single Zinnfile
+pub struct Scope {
+    /// Directory of the Zinnfile, in which its jobs run
+    dir: PathBuf,
+
+    /// Path of the Zinnfile relative to the top-level one (empty for the top-level Zinnfile)
+    label: String,
+
+    jobs: HashMap<String, JobDescription>,
+    constants: HashMap<String, String>,
+
+    /// Environment variables for all jobs (not rendered yet)
+    env: HashMap<String, String>,
+
+    shell: Option<Shell>,
+}
+
+
+impl<'a> Workspace<'a> {
+    /// Create a workspace around the top-level Zinnfile at `path`
+    ///
+    /// The `overrides` are applied to the constants of every Zinnfile.
+    pub fn new(path: &Path, zinnfile: Zinnfile, overrides: Vec<(String, String)>, handlebars: Handlebars<'a>) -> ZinnResult<(Self, Arc<Scope>)> {
+        let canonic_path = path.canonicalize()?;
+        let root = canonic_path.parent().ok_or(ZinnError::ChdirError())?.to_owned();
+        let mut workspace = Workspace { root: root.clone(), handlebars, overrides, scopes: HashMap::new() };
+        let scope = workspace.add_scope(root, String::new(), zinnfile)?;
+        workspace.scopes.insert(canonic_path, scope.clone());
+        Ok((workspace, scope))
+    }
+
+    /// Find the Zinnfile and name of a job referenced from within `scope`
+    ///
+    /// Jobs of other Zinnfiles are referenced with the path to the file relative to the
+    /// current one, followed by a colon and the job name.
+    pub fn lookup(&mut self, scope: &Arc<Scope>, reference: &str) -> ZinnResult<(Arc<Scope>, String)> {
+        match reference.rsplit_once(FILE_SEPARATOR) {
+            Some((file, name)) => Ok((self.load(&scope.dir.join(file))?, name.to_owned())),
+            None => Ok((scope.clone(), reference.to_owned())),
+        }
+    }
+
+    /// Render a template of a Zinnfile
+    pub fn render(&mut self, scope: &Scope, path: &[&str], template: &str, context: &HashMap<String, String>) -> ZinnResult<String> {
+        let mut full_path = Vec::with_capacity(path.len() + 1);
+        if !scope.label.is_empty() {
+            full_path.push(scope.label.as_str());
+        }
+        full_path.extend_from_slice(path);
+        render_component(&full_path, template, &mut self.handlebars, context)
+    }
+
+    fn load(&mut self, path: &Path) -> ZinnResult<Arc<Scope>> {
+        let display_path = path.strip_prefix(&self.root).unwrap_or(path).display().to_string();
+        let canonic_path = path.canonicalize()
+            .map_err(|e| ZinnError::Zinnfile(display_path.clone(), Box::new(e.into())))?;
+        if let Some(scope) = self.scopes.get(&canonic_path) {
+            return Ok(scope.clone());
+        }
+
+        let zinnfile = Zinnfile::load(&canonic_path)
+            .map_err(|e| ZinnError::Zinnfile(display_path, Box::new(e)))?;
+        let dir = canonic_path.parent().ok_or(ZinnError::ChdirError())?.to_owned();
+        let label = canonic_path.strip_prefix(&self.root)
+            .unwrap_or(&canonic_path)
+            .display()
+            .to_string();
+
+        let scope = self.add_scope(dir, label, zinnfile)?;
+        self.scopes.insert(canonic_path, scope.clone());
+        Ok(scope)
+    }
+
+    fn add_scope(&mut self, dir: PathBuf, label: String, zinnfile: Zinnfile) -> ZinnResult<Arc<Scope>> {
+        let mut scope = Scope { dir, label, jobs: zinnfile.jobs, constants: HashMap::new(), env: zinnfile.env, shell: zinnfile.shell };
+
+        // values of dotenv files come first and may be overridden by regular constants
+        let mut dotenv = HashMap::new();
+        for file in &zinnfile.dotenv {
+            dotenv.extend(constants::load_dotenv(Path::new(file))?);
+        }
+        let mut constants = dotenv.clone();
+
+        // all constants are rendered, with all previous constants being available
+        // (the index keeps templates of constants defined more than once apart)
+        for (i, (name, value)) in zinnfile.constants.iter().enumerate() {
+            let template_path = ["constants", &i.to_string(), name];
+            let realized = match value {
+                Constant::Value(template) => self.render(&scope, &template_path, template, &constants)?,
+                Constant::Env { env, default } => match env::var(env).ok().or_else(|| dotenv.get(env).cloned()) {
+                    Some(value) => value,
+                    None => match default {
+                        Some(template) => self.render(&scope, &template_path, template, &constants)?,
+                        None => return Err(ZinnError::MissingEnvVar(env.to_owned())),
+                    },
+                },
+            };
+            constants.insert(name.to_owned(), realized);
+        }
+        let overrides = self.overrides.clone();
+        for (name, value) in &overrides {
+            let realized = self.render(&scope, &["override-const", name], value, &constants)?;
+            constants.insert(name.to_owned(), realized);
+        }
+        scope.constants = constants;
+
+        Ok(Arc::new(scope))
+    }
+}
+
+impl Scope {
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn jobs(&self) -> &HashMap<String, JobDescription> {
+        &self.jobs
+    }
+
+    pub fn constants(&self) -> &HashMap<String, String> {
+        &self.constants
+    }
+
+    pub fn env(&self) -> &HashMap<String, String> {
+        &self.env
+    }
+
+    pub fn shell(&self) -> Option<&Shell> {
+        self.shell.as_ref()
+    }
+
+    /// Name of a job of this Zinnfile as seen from the top-level Zinnfile
+    pub fn job_name(&self, name: &str) -> String {
+        if self.label.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}{}{}", self.label, FILE_SEPARATOR, name)
+        }
+    }
+}